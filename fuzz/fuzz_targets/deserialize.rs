@@ -0,0 +1,12 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tftpd::{Convert, Packet};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Packet::deserialize(data);
+
+    for start in 0..=data.len() + 1 {
+        let _ = Convert::to_string(data, start);
+    }
+});
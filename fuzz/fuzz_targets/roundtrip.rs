@@ -0,0 +1,13 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use tftpd::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = Packet::deserialize(data) else {
+        return;
+    };
+    let buf = packet.serialize().unwrap();
+
+    assert_eq!(Packet::deserialize(&buf).unwrap(), packet);
+});
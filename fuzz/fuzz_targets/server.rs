@@ -0,0 +1,47 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::{
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Mutex,
+};
+use tftpd::{Config, MemorySocket, Server};
+
+// Every packet the server sends is recorded by the in-memory socket. The
+// directory does not exist, so no file can be read or created and accepted
+// requests fail before starting a transfer, leaving no threads, sockets or
+// files behind.
+static SERVER: Mutex<Option<(Server, MemorySocket)>> = Mutex::new(None);
+
+fuzz_target!(|data: &[u8]| {
+    let mut server = SERVER.lock().unwrap();
+    let (server, socket) = server.get_or_insert_with(|| {
+        let from = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9);
+        let socket = MemorySocket::new(from);
+        let config = Config {
+            directory: env::temp_dir().join("tftpd_fuzz_missing"),
+            ..Config::default()
+        };
+        let factory = socket.clone();
+
+        let server = Server::with_socket_factory(&config, move |to| Box::new(factory.connect(*to)));
+        (server, socket)
+    });
+
+    // Inputs may contain several datagrams separated by a two byte length.
+    let from = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9);
+    let mut datagrams = 0;
+    let mut rest = data;
+    while rest.len() >= 2 {
+        let len = (u16::from_be_bytes([rest[0], rest[1]]) as usize).min(rest.len() - 2);
+        server.handle_datagram(&rest[2..2 + len], from);
+        datagrams += 1;
+        rest = &rest[2 + len..];
+    }
+
+    // A datagram is answered with at most an acknowledgement and an error,
+    // and leaves nothing for the server to keep track of.
+    assert!(socket.take_sent().len() <= 2 * datagrams);
+    assert_eq!(server.tracked(), 0);
+});
@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::{env, process};
+use tftpd::{Client, ClientConfig, Rollover, TransferMode};
+
+const DEFAULT_PORT: u16 = 69;
+
+fn main() {
+    let command = Command::new(env::args()).unwrap_or_else(|err| {
+        eprintln!("Problem parsing arguments: {err}");
+        process::exit(1)
+    });
+
+    let client = Client::new(&command.config);
+    let result = match command.action {
+        Action::Get => client.get(&command.remote_file, &command.local_file),
+        Action::Put => client.put(&command.local_file, &command.remote_file),
+    };
+
+    match result {
+        Ok(report) => {
+            let verb = match command.action {
+                Action::Get => "Received",
+                Action::Put => "Sent",
+            };
+            println!(
+                "{verb} {} bytes in {:.2?} ({} blocks, {} retransmissions)",
+                report.bytes, report.duration, report.blocks, report.retransmissions
+            );
+        }
+        Err(err) => {
+            eprintln!("Transfer failed: {err}");
+            process::exit(1)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Get,
+    Put,
+}
+
+/// Command `struct` is used for parsing client options from user input.
+struct Command {
+    action: Action,
+    config: ClientConfig,
+    remote_file: String,
+    local_file: PathBuf,
+}
+
+impl Command {
+    fn new<T: Iterator<Item = String>>(mut args: T) -> Result<Command, Box<dyn Error>> {
+        let mut positional = vec![];
+        let mut mode = TransferMode::Octet;
+        let mut block_size = None;
+        let mut window_size = None;
+        let mut timeout = None;
+        let mut transfer_size = false;
+        let mut rollover = None;
+        let mut trace = false;
+
+        args.next();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-b" | "--blksize" => {
+                    if let Some(size_str) = args.next() {
+                        block_size = Some(size_str.parse::<usize>()?);
+                    } else {
+                        return Err("Missing block size after flag".into());
+                    }
+                }
+                "-w" | "--windowsize" => {
+                    if let Some(size_str) = args.next() {
+                        window_size = Some(size_str.parse::<u16>()?);
+                    } else {
+                        return Err("Missing window size after flag".into());
+                    }
+                }
+                "-t" | "--timeout" => {
+                    if let Some(timeout_str) = args.next() {
+                        timeout = Some(Duration::try_from_secs_f64(timeout_str.parse::<f64>()?)?);
+                    } else {
+                        return Err("Missing timeout after flag".into());
+                    }
+                }
+                "-m" | "--mode" => {
+                    if let Some(mode_str) = args.next() {
+                        mode = TransferMode::from(mode_str.as_str());
+                        if !mode.is_supported() {
+                            return Err(format!("Unsupported mode: {mode_str}").into());
+                        }
+                    } else {
+                        return Err("Missing mode after flag".into());
+                    }
+                }
+                "--tsize" => {
+                    transfer_size = true;
+                }
+                "--rollover" => {
+                    if let Some(rollover_str) = args.next() {
+                        rollover = Some(rollover_str.parse::<Rollover>()?);
+                    } else {
+                        return Err("Missing rollover after flag".into());
+                    }
+                }
+                "-v" | "--verbose" => {
+                    trace = true;
+                }
+                "-h" | "--help" => {
+                    println!("TFTP Client\n");
+                    println!("Usage: tftp [OPTIONS] get <HOST[:PORT]> <REMOTE FILE> [LOCAL FILE]");
+                    println!(
+                        "       tftp [OPTIONS] put <HOST[:PORT]> <LOCAL FILE> [REMOTE FILE]\n"
+                    );
+                    println!("Options:");
+                    println!("  -b, --blksize <SIZE>\t\tRequest a block size (default: 512)");
+                    println!("  -w, --windowsize <SIZE>\tRequest a window size (default: 1)");
+                    println!("  -t, --timeout <SECONDS>\tRequest a timeout, fractions are sent in milliseconds (default: 5)");
+                    println!("  -m, --mode <MODE>\t\tSet the transfer mode, octet or netascii (default: octet)");
+                    println!("  --tsize\t\t\tExchange the transfer size with the server (default: false)");
+                    println!("  --rollover <0|1|none>\t\tRequest block number rollover to 0 or 1, or refuse larger files");
+                    println!("  -v, --verbose\t\t\tPrint every sent and received packet");
+                    println!("  -h, --help\t\t\tPrint help information");
+                    process::exit(0);
+                }
+                flag if flag.starts_with('-') && flag.len() > 1 => {
+                    return Err(format!("Invalid flag: {flag}").into())
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let mut positional = positional.into_iter();
+        let action = match positional.next().as_deref() {
+            Some("get") => Action::Get,
+            Some("put") => Action::Put,
+            Some(invalid) => return Err(format!("Invalid command: {invalid}").into()),
+            None => return Err("Missing command".into()),
+        };
+        let server = match positional.next() {
+            Some(host) => resolve(&host)?,
+            None => return Err("Missing server address".into()),
+        };
+        let file = positional.next().ok_or("Missing file name")?;
+        let other_file = positional.next();
+        if let Some(extra) = positional.next() {
+            return Err(format!("Unexpected argument: {extra}").into());
+        }
+
+        let (remote_file, local_file) = match action {
+            Action::Get => {
+                let local_file = match other_file {
+                    Some(local_file) => PathBuf::from(local_file),
+                    None => PathBuf::from(
+                        Path::new(&file)
+                            .file_name()
+                            .ok_or("Missing local file name")?,
+                    ),
+                };
+                (file, local_file)
+            }
+            Action::Put => {
+                let remote_file = match other_file {
+                    Some(remote_file) => remote_file,
+                    None => Path::new(&file)
+                        .file_name()
+                        .ok_or("Missing remote file name")?
+                        .to_string_lossy()
+                        .to_string(),
+                };
+                (remote_file, PathBuf::from(file))
+            }
+        };
+
+        let mut config = ClientConfig::new(server);
+        config.mode = mode;
+        config.block_size = block_size;
+        config.window_size = window_size;
+        config.timeout = timeout;
+        config.transfer_size = transfer_size;
+        config.rollover = rollover;
+        config.trace = trace;
+
+        Ok(Command {
+            action,
+            config,
+            remote_file,
+            local_file,
+        })
+    }
+}
+
+/// Resolves `host` to a [`SocketAddr`], using the default TFTP port if
+/// no port is supplied.
+fn resolve(host: &str) -> Result<SocketAddr, Box<dyn Error>> {
+    let addrs = match host.to_socket_addrs() {
+        Ok(addrs) => addrs,
+        Err(_) => (host, DEFAULT_PORT).to_socket_addrs()?,
+    };
+
+    addrs
+        .into_iter()
+        .next()
+        .ok_or_else(|| format!("Could not resolve {host}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn parses_get_command() {
+        let command = Command::new(
+            [
+                "/",
+                "-b",
+                "1024",
+                "-w",
+                "4",
+                "-t",
+                "2",
+                "-m",
+                "netascii",
+                "--rollover",
+                "1",
+                "-v",
+                "get",
+                "127.0.0.1:6969",
+                "dir/remote.txt",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(command.action, Action::Get);
+        assert_eq!(
+            command.config.server,
+            SocketAddr::from_str("127.0.0.1:6969").unwrap()
+        );
+        assert_eq!(command.config.block_size, Some(1024));
+        assert_eq!(command.config.window_size, Some(4));
+        assert_eq!(command.config.timeout, Some(Duration::from_secs(2)));
+        assert_eq!(command.config.mode, TransferMode::Netascii);
+        assert_eq!(command.config.rollover, Some(Rollover::One));
+        assert!(command.config.trace);
+        assert_eq!(command.remote_file, "dir/remote.txt");
+        assert_eq!(command.local_file, PathBuf::from("remote.txt"));
+    }
+
+    #[test]
+    fn parses_put_command() {
+        let command = Command::new(
+            [
+                "/",
+                "-t",
+                "0.25",
+                "put",
+                "::1",
+                "/tmp/local.txt",
+                "remote.txt",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(command.action, Action::Put);
+        assert_eq!(
+            command.config.server,
+            SocketAddr::from_str("[::1]:69").unwrap()
+        );
+        assert_eq!(command.config.block_size, None);
+        assert_eq!(command.config.timeout, Some(Duration::from_millis(250)));
+        assert_eq!(command.remote_file, "remote.txt");
+        assert_eq!(command.local_file, PathBuf::from("/tmp/local.txt"));
+    }
+
+    #[test]
+    fn rejects_invalid_commands() {
+        for args in [
+            vec!["/"],
+            vec!["/", "fetch", "127.0.0.1", "file"],
+            vec!["/", "get", "127.0.0.1"],
+            vec!["/", "-m", "mail", "get", "127.0.0.1", "file"],
+            vec!["/", "put", "127.0.0.1", "a", "b", "c"],
+        ] {
+            assert!(Command::new(args.iter().map(|s| s.to_string())).is_err());
+        }
+    }
+}
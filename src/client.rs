@@ -0,0 +1,710 @@
+use crate::packet::MIN_BLOCK_SIZE;
+use crate::socket::MAX_REQUEST_PACKET_SIZE;
+use crate::window::netascii_len;
+use crate::worker::MAX_RETRIES;
+use crate::{ErrorCode, OptionType, Packet, Rollover, Socket, TftpError, TransferReport, Worker};
+use crate::{TransferMode, TransferOption};
+use std::fs::File;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::path::Path;
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_BLOCK_SIZE: usize = 512;
+const DEFAULT_WINDOW_SIZE: u16 = 1;
+
+/// ClientConfig `struct` contains the server address and the transfer
+/// options used by a [`Client`].
+///
+/// Options set to [`None`] are not sent to the server, and the defaults
+/// of RFC 1350 are used for them.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{net::SocketAddr, str::FromStr, time::Duration};
+/// use tftpd::ClientConfig;
+///
+/// let mut config = ClientConfig::new(SocketAddr::from_str("127.0.0.1:69").unwrap());
+/// config.block_size = Some(1428);
+/// config.timeout = Some(Duration::from_secs(2));
+/// ```
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// Address of the TFTP server
+    pub server: SocketAddr,
+    /// Transfer mode
+    pub mode: TransferMode,
+    /// Requested block size
+    pub block_size: Option<usize>,
+    /// Requested window size
+    pub window_size: Option<u16>,
+    /// Requested timeout, also used as the local retransmission timeout
+    pub timeout: Option<Duration>,
+    /// Whether the transfer size is exchanged with the server
+    pub transfer_size: bool,
+    /// Requested block number rollover, [`Rollover::Disabled`] is not sent
+    pub rollover: Option<Rollover>,
+    /// Whether every sent and received packet is printed
+    pub trace: bool,
+}
+
+impl ClientConfig {
+    /// Creates a new [`ClientConfig`] for the server at the supplied
+    /// [`SocketAddr`], using octet mode and no options.
+    pub fn new(server: SocketAddr) -> ClientConfig {
+        ClientConfig {
+            server,
+            mode: TransferMode::Octet,
+            block_size: None,
+            window_size: None,
+            timeout: None,
+            transfer_size: false,
+            rollover: None,
+            trace: false,
+        }
+    }
+}
+
+/// Client `struct` is used for downloading files from and uploading files
+/// to a TFTP server.
+///
+/// Each transfer uses a new socket bound to a random port, and runs on the
+/// calling thread until it finishes.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// use std::{net::SocketAddr, path::Path, str::FromStr};
+/// use tftpd::{Client, ClientConfig};
+///
+/// let config = ClientConfig::new(SocketAddr::from_str("127.0.0.1:69").unwrap());
+/// let client = Client::new(&config);
+///
+/// let report = client.get("remote.txt", Path::new("local.txt")).unwrap();
+/// println!("Received {} bytes", report.bytes);
+/// ```
+pub struct Client {
+    config: ClientConfig,
+}
+
+impl Client {
+    /// Creates a new [`Client`] with the supplied [`ClientConfig`].
+    pub fn new(config: &ClientConfig) -> Client {
+        Client {
+            config: config.clone(),
+        }
+    }
+
+    /// Downloads `remote_file` from the server and saves it to `local_file`.
+    /// Returns the [`TransferReport`] of the transfer, or the error that ended
+    /// it. If the server answers with an error packet, a
+    /// [`TftpError::Remote`] is returned.
+    pub fn get(&self, remote_file: &str, local_file: &Path) -> Result<TransferReport, TftpError> {
+        self.check_mode()?;
+        let socket = self.create_socket()?;
+        let requested = self.options(0);
+        let request = Packet::Rrq {
+            filename: remote_file.to_string(),
+            mode: self.config.mode.clone(),
+            options: requested.clone(),
+        };
+
+        let (response, from) = self.request(&socket, &request)?;
+        let options = match response {
+            Packet::Oack(acknowledged) => {
+                socket.discard()?;
+                socket.connect(from)?;
+                let options = self.accept_options(&socket, &requested, &acknowledged)?;
+                socket.send(&Packet::Ack(0))?;
+
+                options
+            }
+            // The first data packet is left queued for the worker.
+            Packet::Data { .. } => {
+                socket.connect(from)?;
+
+                self.default_options()
+            }
+            response => return Err(self.reject(&socket, response, from)?),
+        };
+
+        let worker = self.create_worker(socket, local_file, options)?;
+        finish(worker.receive()?.join())
+    }
+
+    /// Uploads `local_file` to the server, saving it as `remote_file`.
+    /// Returns the [`TransferReport`] of the transfer, or the error that ended
+    /// it. If the server answers with an error packet, a
+    /// [`TftpError::Remote`] is returned.
+    pub fn put(&self, local_file: &Path, remote_file: &str) -> Result<TransferReport, TftpError> {
+        self.check_mode()?;
+        let file_size = if self.config.mode == TransferMode::Netascii {
+            netascii_len(&mut File::open(local_file)?)?
+        } else {
+            local_file.metadata()?.len()
+        };
+
+        let socket = self.create_socket()?;
+        let requested = self.options(file_size);
+        let request = Packet::Wrq {
+            filename: remote_file.to_string(),
+            mode: self.config.mode.clone(),
+            options: requested.clone(),
+        };
+
+        let (response, from) = self.request(&socket, &request)?;
+        let options = match response {
+            Packet::Oack(acknowledged) => {
+                socket.discard()?;
+                socket.connect(from)?;
+
+                self.accept_options(&socket, &requested, &acknowledged)?
+            }
+            Packet::Ack(0) => {
+                socket.discard()?;
+                socket.connect(from)?;
+
+                self.default_options()
+            }
+            response => return Err(self.reject(&socket, response, from)?),
+        };
+
+        let worker = self.create_worker(socket, local_file, options)?;
+        finish(worker.send()?.join())
+    }
+
+    fn check_mode(&self) -> Result<(), TftpError> {
+        if self.config.mode.is_supported() {
+            Ok(())
+        } else {
+            Err(TftpError::Protocol(format!(
+                "Unsupported transfer mode {}",
+                self.config.mode
+            )))
+        }
+    }
+
+    fn create_socket(&self) -> Result<ClientSocket, TftpError> {
+        let socket = if self.config.server.is_ipv4() {
+            UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?
+        } else {
+            UdpSocket::bind((Ipv6Addr::UNSPECIFIED, 0))?
+        };
+        socket.set_read_timeout(Some(self.timeout()))?;
+
+        Ok(ClientSocket {
+            socket,
+            trace: self.config.trace,
+        })
+    }
+
+    fn create_worker(
+        &self,
+        mut socket: ClientSocket,
+        file: &Path,
+        options: ClientOptions,
+    ) -> Result<Worker<ClientSocket>, TftpError> {
+        socket.set_read_timeout(options.timeout)?;
+        socket.set_write_timeout(options.timeout)?;
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            file.to_path_buf(),
+            options.block_size,
+            options.timeout,
+            options.window_size,
+            self.config.mode == TransferMode::Netascii,
+        );
+        worker.set_rollover(options.rollover);
+
+        Ok(worker)
+    }
+
+    fn options(&self, transfer_size: u64) -> Vec<TransferOption> {
+        let mut options = vec![];
+
+        if let Some(block_size) = self.config.block_size {
+            options.push(TransferOption {
+                option: OptionType::BlockSize,
+                value: block_size,
+            });
+        }
+        if self.config.transfer_size {
+            options.push(TransferOption {
+                option: OptionType::TransferSize,
+                value: transfer_size as usize,
+            });
+        }
+        if let Some(timeout) = self.config.timeout {
+            // Timeouts with a fraction of a second are requested in milliseconds.
+            options.push(if timeout.subsec_nanos() == 0 {
+                TransferOption {
+                    option: OptionType::Timeout,
+                    value: timeout.as_secs().max(1) as usize,
+                }
+            } else {
+                TransferOption {
+                    option: OptionType::TimeoutMs,
+                    value: timeout.as_millis().max(1) as usize,
+                }
+            });
+        }
+        if let Some(window_size) = self.config.window_size {
+            options.push(TransferOption {
+                option: OptionType::Windowsize,
+                value: window_size as usize,
+            });
+        }
+        if let Some(value) = self.config.rollover.and_then(Rollover::value) {
+            options.push(TransferOption {
+                option: OptionType::Rollover,
+                value,
+            });
+        }
+
+        options
+    }
+
+    fn timeout(&self) -> Duration {
+        self.config.timeout.unwrap_or(DEFAULT_TIMEOUT)
+    }
+
+    fn default_options(&self) -> ClientOptions {
+        // Block numbers wrap to 0 unless the server acknowledges another
+        // rollover. Disabling rollover only limits the local side.
+        let rollover = match self.config.rollover {
+            Some(Rollover::Disabled) => Rollover::Disabled,
+            _ => Rollover::default(),
+        };
+
+        ClientOptions {
+            block_size: DEFAULT_BLOCK_SIZE,
+            timeout: self.timeout(),
+            window_size: DEFAULT_WINDOW_SIZE,
+            rollover,
+        }
+    }
+
+    /// Sends the request until the server responds, and returns the response
+    /// without removing it from the socket.
+    fn request(
+        &self,
+        socket: &ClientSocket,
+        request: &Packet,
+    ) -> Result<(Packet, SocketAddr), TftpError> {
+        // The response is either a data packet or an option acknowledgement,
+        // which may not fit in a small block.
+        let size = self
+            .config
+            .block_size
+            .unwrap_or(DEFAULT_BLOCK_SIZE)
+            .max(MAX_REQUEST_PACKET_SIZE);
+
+        for _ in 0..MAX_RETRIES {
+            socket.send_to(request, &self.config.server)?;
+
+            loop {
+                match socket.peek_from(size) {
+                    Ok(Some(response)) => return Ok(response),
+                    Ok(None) => socket.discard()?,
+                    Err(TftpError::Timeout) => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+
+        Err(TftpError::Timeout)
+    }
+
+    fn accept_options(
+        &self,
+        socket: &ClientSocket,
+        requested: &[TransferOption],
+        acknowledged: &[TransferOption],
+    ) -> Result<ClientOptions, TftpError> {
+        let mut options = self.default_options();
+
+        for option in acknowledged {
+            let requested = requested
+                .iter()
+                .find(|requested| requested.option == option.option);
+            let valid = match (option.option, requested) {
+                (_, None) | (OptionType::Multicast(_), _) => false,
+                (OptionType::BlockSize, Some(requested)) => {
+                    options.block_size = option.value;
+                    (MIN_BLOCK_SIZE..=requested.value).contains(&option.value)
+                }
+                (OptionType::TransferSize, Some(_)) => true,
+                (
+                    OptionType::Timeout | OptionType::TimeoutMs | OptionType::Utimeout,
+                    Some(requested),
+                ) => {
+                    options.timeout = option.option.timeout(option.value).unwrap_or_default();
+                    option.value == requested.value
+                }
+                (OptionType::Windowsize, Some(requested)) => {
+                    options.window_size = option.value as u16;
+                    (1..=requested.value).contains(&option.value)
+                }
+                (OptionType::Rollover, Some(requested)) => {
+                    options.rollover = Rollover::from_value(option.value).unwrap_or_default();
+                    option.value == requested.value
+                }
+            };
+
+            if !valid {
+                let msg = format!("invalid {} option acknowledgement", option.option.as_str());
+                socket.send(&Packet::Error {
+                    code: ErrorCode::OptionNegotiation,
+                    msg: msg.clone(),
+                })?;
+
+                return Err(TftpError::OptionNegotiation(msg));
+            }
+        }
+
+        Ok(options)
+    }
+
+    fn reject(
+        &self,
+        socket: &ClientSocket,
+        response: Packet,
+        from: SocketAddr,
+    ) -> Result<TftpError, TftpError> {
+        socket.discard()?;
+
+        if let Packet::Error { code, msg } = response {
+            return Ok(TftpError::Remote { code, msg });
+        }
+
+        socket.send_to(
+            &Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                msg: "unexpected response".to_string(),
+            },
+            &from,
+        )?;
+
+        Ok(TftpError::Protocol("Unexpected response".to_string()))
+    }
+}
+
+struct ClientOptions {
+    block_size: usize,
+    timeout: Duration,
+    window_size: u16,
+    rollover: Rollover,
+}
+
+/// UDP socket used by a [`Client`], optionally printing every packet
+/// that passes through it.
+struct ClientSocket {
+    socket: UdpSocket,
+    trace: bool,
+}
+
+impl ClientSocket {
+    fn connect(&self, remote: SocketAddr) -> Result<(), TftpError> {
+        self.socket.connect(remote)?;
+
+        Ok(())
+    }
+
+    /// Returns the first queued packet without removing it from the socket,
+    /// or [`None`] if it is malformed. Data packets are only printed once
+    /// they are received.
+    fn peek_from(&self, size: usize) -> Result<Option<(Packet, SocketAddr)>, TftpError> {
+        let mut buf = vec![0; size + 4];
+        let (amt, from) = self.socket.peek_from(&mut buf)?;
+
+        match Packet::deserialize(&buf[..amt]) {
+            Ok(packet) => {
+                if !matches!(packet, Packet::Data { .. }) {
+                    self.trace("received", &packet);
+                }
+                Ok(Some((packet, from)))
+            }
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Removes the first queued datagram from the socket.
+    fn discard(&self) -> Result<(), TftpError> {
+        self.socket.recv_from(&mut [])?;
+
+        Ok(())
+    }
+
+    fn trace(&self, direction: &str, packet: &Packet) {
+        if self.trace {
+            println!("{direction} {packet}");
+        }
+    }
+}
+
+impl Socket for ClientSocket {
+    fn send(&self, packet: &Packet) -> Result<(), TftpError> {
+        self.trace("sent", packet);
+        Socket::send(&self.socket, packet)
+    }
+
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError> {
+        self.trace("sent", packet);
+        Socket::send_to(&self.socket, packet, to)
+    }
+
+    fn recv_with_size(&self, size: usize) -> Result<Packet, TftpError> {
+        let packet = self.socket.recv_with_size(size)?;
+        self.trace("received", &packet);
+
+        Ok(packet)
+    }
+
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), TftpError> {
+        let (packet, from) = self.socket.recv_from_with_size(size)?;
+        self.trace("received", &packet);
+
+        Ok((packet, from))
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
+        self.socket.remote_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
+        Socket::set_read_timeout(&mut self.socket, dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
+        Socket::set_write_timeout(&mut self.socket, dur)
+    }
+}
+
+fn finish(mut report: TransferReport) -> Result<TransferReport, TftpError> {
+    match report.error.take() {
+        Some(err) => Err(err),
+        None => Ok(report),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Config, Server, TransferDirection};
+    use std::{
+        env, fs,
+        net::IpAddr,
+        path::{Path, PathBuf},
+        thread,
+    };
+
+    #[test]
+    fn gets_file_without_options() {
+        let directory = initialize("gets_file_without_options");
+        fs::write(directory.join("hello.txt"), [0x01; 1000]).unwrap();
+        let client = Client::new(&ClientConfig::new(start_server(&directory)));
+
+        let local_file = directory.join("local.txt");
+        let report = client.get("hello.txt", &local_file).unwrap();
+
+        assert_eq!(report.direction, TransferDirection::Receive);
+        assert_eq!(report.bytes, 1000);
+        assert_eq!(report.blocks, 2);
+        assert_eq!(fs::read(local_file).unwrap(), [0x01; 1000]);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn gets_file_with_options() {
+        let directory = initialize("gets_file_with_options");
+        let contents = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(directory.join("hello.txt"), &contents).unwrap();
+        let mut config = ClientConfig::new(start_server(&directory));
+        config.block_size = Some(1024);
+        config.window_size = Some(4);
+        config.timeout = Some(Duration::from_secs(1));
+        config.transfer_size = true;
+        let client = Client::new(&config);
+
+        let local_file = directory.join("local.txt");
+        let report = client.get("hello.txt", &local_file).unwrap();
+
+        assert_eq!(report.bytes, 5000);
+        assert_eq!(report.blocks, 5);
+        assert_eq!(fs::read(local_file).unwrap(), contents);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn gets_file_with_millisecond_timeout() {
+        let directory = initialize("gets_file_with_millisecond_timeout");
+        fs::write(directory.join("hello.txt"), [0x01; 2000]).unwrap();
+        let mut config = ClientConfig::new(start_server(&directory));
+        config.timeout = Some(Duration::from_millis(200));
+        let client = Client::new(&config);
+
+        let local_file = directory.join("local.txt");
+        let report = client.get("hello.txt", &local_file).unwrap();
+
+        assert_eq!(report.bytes, 2000);
+        assert_eq!(fs::read(local_file).unwrap(), [0x01; 2000]);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn puts_file_without_options() {
+        let directory = initialize("puts_file_without_options");
+        let local_file = directory.join("local.txt");
+        fs::write(&local_file, [0x02; 700]).unwrap();
+        let client = Client::new(&ClientConfig::new(start_server(&directory)));
+
+        let report = client.put(&local_file, "hello.txt").unwrap();
+
+        assert_eq!(report.direction, TransferDirection::Send);
+        assert_eq!(report.bytes, 700);
+        assert_eq!(fs::read(directory.join("hello.txt")).unwrap(), [0x02; 700]);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn puts_file_with_options() {
+        let directory = initialize("puts_file_with_options");
+        let local_file = directory.join("local.txt");
+        let contents = (0..3000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        fs::write(&local_file, &contents).unwrap();
+        let mut config = ClientConfig::new(start_server(&directory));
+        config.block_size = Some(1024);
+        config.window_size = Some(2);
+        config.transfer_size = true;
+        let client = Client::new(&config);
+
+        let report = client.put(&local_file, "hello.txt").unwrap();
+
+        assert_eq!(report.bytes, 3000);
+        assert_eq!(report.blocks, 3);
+        assert_eq!(fs::read(directory.join("hello.txt")).unwrap(), contents);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn gets_file_with_rollover() {
+        let directory = initialize("gets_file_with_rollover");
+        let contents = (0..8 * 65540).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(directory.join("firmware.bin"), &contents).unwrap();
+        let mut config = ClientConfig::new(start_server(&directory));
+        config.block_size = Some(8);
+        config.window_size = Some(16);
+        config.rollover = Some(Rollover::One);
+        let client = Client::new(&config);
+
+        let local_file = directory.join("local.bin");
+        let report = client.get("firmware.bin", &local_file).unwrap();
+
+        assert_eq!(report.blocks, 65541);
+        assert_eq!(fs::read(local_file).unwrap(), contents);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn gets_file_with_congestion_control() {
+        let directory = initialize("gets_file_with_congestion_control");
+        let contents = (0..8 * 199 + 5)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        fs::write(directory.join("firmware.bin"), &contents).unwrap();
+        let mut config = ClientConfig::new(run_server(&Config {
+            congestion_control: true,
+            ..server_config(&directory)
+        }));
+        config.block_size = Some(8);
+        config.window_size = Some(16);
+        config.timeout = Some(Duration::from_millis(300));
+        let client = Client::new(&config);
+
+        let local_file = directory.join("local.bin");
+        let report = client.get("firmware.bin", &local_file).unwrap();
+
+        assert_eq!(report.blocks, 200);
+        assert_eq!(fs::read(local_file).unwrap(), contents);
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn uses_rollover_only_if_acknowledged() {
+        let mut config = ClientConfig::new(SocketAddr::from((Ipv4Addr::LOCALHOST, 69)));
+        config.rollover = Some(Rollover::One);
+        let client = Client::new(&config);
+        let socket = client.create_socket().unwrap();
+        let requested = client.options(0);
+
+        assert_eq!(client.default_options().rollover, Rollover::Zero);
+        let options = client.accept_options(&socket, &requested, &[]).unwrap();
+        assert_eq!(options.rollover, Rollover::Zero);
+        let options = client
+            .accept_options(&socket, &requested, &requested)
+            .unwrap();
+        assert_eq!(options.rollover, Rollover::One);
+    }
+
+    #[test]
+    fn returns_remote_error() {
+        let directory = initialize("returns_remote_error");
+        let client = Client::new(&ClientConfig::new(start_server(&directory)));
+
+        let result = client.get("missing.txt", &directory.join("local.txt"));
+
+        assert!(matches!(
+            result,
+            Err(TftpError::Remote {
+                code: ErrorCode::FileNotFound,
+                ..
+            })
+        ));
+
+        clean(&directory);
+    }
+
+    fn start_server(directory: &Path) -> SocketAddr {
+        run_server(&server_config(directory))
+    }
+
+    fn run_server(config: &Config) -> SocketAddr {
+        let mut server = Server::new(config).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        thread::spawn(move || server.listen());
+
+        addr
+    }
+
+    fn server_config(directory: &Path) -> Config {
+        Config {
+            ip_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            directory: directory.to_path_buf(),
+            ..Config::default()
+        }
+    }
+
+    fn initialize(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("tftpd_{name}"));
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn clean(directory: &Path) {
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,8 +1,13 @@
 use std::error::Error;
-use std::net::Ipv4Addr;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 use std::{env, process};
 
+use crate::packet::{MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use crate::worker::MAX_RETRIES;
+use crate::Rollover;
+
 /// Configuration `struct` used for parsing TFTP options from user
 /// input.
 ///
@@ -20,25 +25,65 @@ use std::{env, process};
 /// ```
 pub struct Config {
     /// Local IP address of the TFTP Server. (default: 127.0.0.1)
-    pub ip_address: Ipv4Addr,
+    pub ip_address: IpAddr,
     /// Local Port number of the TFTP Server. (default: 69)
     pub port: u16,
+    /// Additional local addresses the TFTP Server listens on. (default: none)
+    pub listen_addresses: Vec<SocketAddr>,
     /// Default directory of the TFTP Server. (default: current working directory)
     pub directory: PathBuf,
     /// Use a single port for both sending and receiving. (default: false)
     pub single_port: bool,
+    /// Accept IPv4 clients when listening on an IPv6 address. (default: false)
+    pub dual_stack: bool,
+    /// Multicast group offered to clients requesting the multicast option. (default: none)
+    pub multicast: Option<SocketAddr>,
+    /// Largest block size offered to clients, smaller requests are acknowledged as is. (default: 65464)
+    pub max_block_size: usize,
+    /// Block number rollover used when the client does not request one. (default: 0)
+    pub rollover: Rollover,
+    /// Estimate the retransmission timeout unless the client requests one. (default: false)
+    pub adaptive_timeout: bool,
+    /// Smallest adaptive retransmission timeout. (default: 100ms)
+    pub min_timeout: Duration,
+    /// Largest adaptive retransmission timeout. (default: 10s)
+    pub max_timeout: Duration,
+    /// Number of retries before a transfer is abandoned. (default: 6)
+    pub retries: u32,
+    /// Adapt the number of blocks sent at once to losses. (default: false)
+    pub congestion_control: bool,
+    /// Time to keep acknowledging a repeated last block of an upload, the transfer timeout if unset. (default: none)
+    pub dally: Option<Duration>,
+}
+
+impl Default for Config {
+    /// Returns the configuration used when no arguments are supplied.
+    fn default() -> Config {
+        Config {
+            ip_address: IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port: 69,
+            listen_addresses: Vec::new(),
+            directory: env::current_dir().unwrap_or_else(|_| env::temp_dir()),
+            single_port: false,
+            dual_stack: false,
+            multicast: None,
+            max_block_size: MAX_BLOCK_SIZE,
+            rollover: Rollover::default(),
+            adaptive_timeout: false,
+            min_timeout: Duration::from_millis(100),
+            max_timeout: Duration::from_secs(10),
+            retries: MAX_RETRIES,
+            congestion_control: false,
+            dally: None,
+        }
+    }
 }
 
 impl Config {
     /// Creates a new configuration by parsing the supplied arguments. It is
     /// intended for use with [`env::args()`].
     pub fn new<T: Iterator<Item = String>>(mut args: T) -> Result<Config, Box<dyn Error>> {
-        let mut config = Config {
-            ip_address: Ipv4Addr::new(127, 0, 0, 1),
-            port: 69,
-            directory: env::current_dir().unwrap_or_else(|_| env::temp_dir()),
-            single_port: false,
-        };
+        let mut config = Config::default();
 
         args.next();
 
@@ -46,7 +91,7 @@ impl Config {
             match arg.as_str() {
                 "-i" | "--ip-address" => {
                     if let Some(ip_str) = args.next() {
-                        config.ip_address = ip_str.parse::<Ipv4Addr>()?;
+                        config.ip_address = ip_str.parse::<IpAddr>()?;
                     } else {
                         return Err("Missing ip address after flag".into());
                     }
@@ -58,6 +103,15 @@ impl Config {
                         return Err("Missing port number after flag".into());
                     }
                 }
+                "-l" | "--listen" => {
+                    if let Some(addr_str) = args.next() {
+                        config
+                            .listen_addresses
+                            .push(addr_str.parse::<SocketAddr>()?);
+                    } else {
+                        return Err("Missing listen address after flag".into());
+                    }
+                }
                 "-d" | "--directory" => {
                     if let Some(dir_str) = args.next() {
                         if !Path::new(&dir_str).exists() {
@@ -71,6 +125,78 @@ impl Config {
                 "-s" | "--single-port" => {
                     config.single_port = true;
                 }
+                "--dual-stack" => {
+                    config.dual_stack = true;
+                }
+                "--multicast" => {
+                    if let Some(addr_str) = args.next() {
+                        let addr = addr_str.parse::<SocketAddr>()?;
+                        if !addr.ip().is_multicast() {
+                            return Err(format!("{addr_str} is not a multicast address").into());
+                        }
+                        config.multicast = Some(addr);
+                    } else {
+                        return Err("Missing multicast address after flag".into());
+                    }
+                }
+                "--max-blksize" => {
+                    if let Some(size_str) = args.next() {
+                        let size = size_str.parse::<usize>()?;
+                        if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&size) {
+                            return Err(format!(
+                                "Block size must be between {MIN_BLOCK_SIZE} and {MAX_BLOCK_SIZE}"
+                            )
+                            .into());
+                        }
+                        config.max_block_size = size;
+                    } else {
+                        return Err("Missing block size after flag".into());
+                    }
+                }
+                "--rollover" => {
+                    if let Some(rollover_str) = args.next() {
+                        config.rollover = rollover_str.parse::<Rollover>()?;
+                    } else {
+                        return Err("Missing rollover after flag".into());
+                    }
+                }
+                "--adaptive-timeout" => {
+                    config.adaptive_timeout = true;
+                }
+                "--min-timeout" => {
+                    if let Some(timeout_str) = args.next() {
+                        config.min_timeout = Duration::from_millis(timeout_str.parse::<u64>()?);
+                    } else {
+                        return Err("Missing minimum timeout after flag".into());
+                    }
+                }
+                "--max-timeout" => {
+                    if let Some(timeout_str) = args.next() {
+                        config.max_timeout = Duration::from_millis(timeout_str.parse::<u64>()?);
+                    } else {
+                        return Err("Missing maximum timeout after flag".into());
+                    }
+                }
+                "--retries" => {
+                    if let Some(retries_str) = args.next() {
+                        config.retries = retries_str.parse::<u32>()?;
+                        if config.retries == 0 {
+                            return Err("Retries must be at least 1".into());
+                        }
+                    } else {
+                        return Err("Missing retries after flag".into());
+                    }
+                }
+                "--congestion-control" => {
+                    config.congestion_control = true;
+                }
+                "--dally" => {
+                    if let Some(dally_str) = args.next() {
+                        config.dally = Some(Duration::from_millis(dally_str.parse::<u64>()?));
+                    } else {
+                        return Err("Missing dally time after flag".into());
+                    }
+                }
                 "-h" | "--help" => {
                     println!("TFTP Server Daemon\n");
                     println!("Usage: tftpd [OPTIONS]\n");
@@ -79,8 +205,23 @@ impl Config {
                     println!(
                         "  -p, --port <PORT>\t\tSet the listening port of the server (default: 69)"
                     );
+                    println!("  -l, --listen <ADDRESS:PORT>\tAlso listen on the given address, can be repeated (default: none)");
                     println!("  -d, --directory <DIRECTORY>\tSet the listening port of the server (default: Current Working Directory)");
                     println!("  -s, --single-port\t\tUse a single port for both sending and receiving (default: false)");
+                    println!("  --dual-stack\t\t\tAccept IPv4 clients on an IPv6 address (default: false)");
+                    println!("  --multicast <ADDRESS:PORT>\tOffer multicast transfers on the given group (default: none)");
+                    println!("  --max-blksize <SIZE>\t\tLimit the block size offered to clients (default: 65464)");
+                    println!("  --rollover <0|1|none>\t\tWrap block numbers to 0 or 1, or refuse larger files (default: 0)");
+                    println!("  --adaptive-timeout\t\tEstimate the retransmission timeout from round trip times (default: false)");
+                    println!(
+                        "  --min-timeout <MS>\t\tSet the smallest adaptive timeout (default: 100)"
+                    );
+                    println!(
+                        "  --max-timeout <MS>\t\tSet the largest adaptive timeout (default: 10000)"
+                    );
+                    println!("  --retries <COUNT>\t\tSet the number of retries before a transfer is abandoned (default: 6)");
+                    println!("  --congestion-control\t\tAdapt the number of blocks sent at once to losses (default: false)");
+                    println!("  --dally <MS>\t\t\tKeep acknowledging a repeated last block of uploads (default: transfer timeout)");
                     println!("  -h, --help\t\t\tPrint help information");
                     process::exit(0);
                 }
@@ -88,12 +229,28 @@ impl Config {
             }
         }
 
+        if config.min_timeout.is_zero() || config.min_timeout > config.max_timeout {
+            return Err(
+                "Minimum timeout must be positive and not above the maximum timeout".into(),
+            );
+        }
+
         Ok(config)
     }
+
+    /// Returns all the addresses the TFTP Server should listen on, starting
+    /// with [`Config::ip_address`] and [`Config::port`].
+    pub fn addresses(&self) -> Vec<SocketAddr> {
+        let mut addresses = vec![SocketAddr::new(self.ip_address, self.port)];
+        addresses.extend(&self.listen_addresses);
+
+        addresses
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::net::Ipv6Addr;
     use std::str::FromStr;
 
     use super::*;
@@ -101,7 +258,7 @@ mod tests {
     #[test]
     fn parses_full_config() {
         let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/", "-s"]
+            ["/", "-i", "0.0.0.0", "-p", "1234", "-d", "/", "-s"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -116,7 +273,7 @@ mod tests {
     #[test]
     fn parses_some_config() {
         let config = Config::new(
-            vec!["/", "-i", "0.0.0.0", "-d", "/"]
+            ["/", "-i", "0.0.0.0", "-d", "/"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -127,10 +284,50 @@ mod tests {
         assert_eq!(config.directory, PathBuf::from_str("/").unwrap());
     }
 
+    #[test]
+    fn parses_ipv6_config() {
+        let config = Config::new(
+            ["/", "-i", "::", "--dual-stack"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(config.ip_address, Ipv6Addr::UNSPECIFIED);
+        assert!(config.dual_stack);
+    }
+
+    #[test]
+    fn parses_listen_addresses() {
+        let config = Config::new(
+            [
+                "/",
+                "-p",
+                "1234",
+                "-l",
+                "10.0.0.1:69",
+                "--listen",
+                "[::1]:6969",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.addresses(),
+            [
+                SocketAddr::from_str("127.0.0.1:1234").unwrap(),
+                SocketAddr::from_str("10.0.0.1:69").unwrap(),
+                SocketAddr::from_str("[::1]:6969").unwrap(),
+            ]
+        );
+    }
+
     #[test]
     fn returns_error_on_invalid_ip() {
         assert!(Config::new(
-            vec!["/", "-i", "1234.5678.9012.3456"]
+            ["/", "-i", "1234.5678.9012.3456"]
                 .iter()
                 .map(|s| s.to_string()),
         )
@@ -139,16 +336,94 @@ mod tests {
 
     #[test]
     fn returns_error_on_invalid_port() {
-        assert!(Config::new(vec!["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
+        assert!(Config::new(["/", "-p", "1234567"].iter().map(|s| s.to_string()),).is_err());
     }
 
     #[test]
     fn returns_error_on_invalid_directory() {
         assert!(Config::new(
-            vec!["/", "-d", "/this/does/not/exist"]
+            ["/", "-d", "/this/does/not/exist"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn parses_multicast_address() {
+        let config = Config::new(
+            ["/", "--multicast", "239.255.0.1:1758"]
+                .iter()
+                .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.multicast,
+            Some(SocketAddr::from_str("239.255.0.1:1758").unwrap())
+        );
+        assert!(Config::new(
+            ["/", "--multicast", "10.0.0.1:1758"]
                 .iter()
                 .map(|s| s.to_string()),
         )
         .is_err());
     }
+
+    #[test]
+    fn parses_rollover() {
+        let config =
+            Config::new(["/", "--rollover", "none"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.rollover, Rollover::Disabled);
+        assert!(Config::new(["/", "--rollover", "2"].iter().map(|s| s.to_string())).is_err());
+    }
+
+    #[test]
+    fn parses_max_block_size() {
+        let config =
+            Config::new(["/", "--max-blksize", "1428"].iter().map(|s| s.to_string())).unwrap();
+
+        assert_eq!(config.max_block_size, 1428);
+        for size in ["0", "7", "65465"] {
+            assert!(
+                Config::new(["/", "--max-blksize", size].iter().map(|s| s.to_string())).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn parses_retransmission_config() {
+        let config = Config::new(
+            [
+                "/",
+                "--adaptive-timeout",
+                "--min-timeout",
+                "20",
+                "--max-timeout",
+                "2000",
+                "--retries",
+                "10",
+                "--congestion-control",
+                "--dally",
+                "500",
+            ]
+            .iter()
+            .map(|s| s.to_string()),
+        )
+        .unwrap();
+
+        assert!(config.adaptive_timeout);
+        assert_eq!(config.min_timeout, Duration::from_millis(20));
+        assert_eq!(config.max_timeout, Duration::from_secs(2));
+        assert_eq!(config.retries, 10);
+        assert!(config.congestion_control);
+        assert_eq!(config.dally, Some(Duration::from_millis(500)));
+        assert!(Config::new(
+            ["/", "--min-timeout", "3000", "--max-timeout", "2000"]
+                .iter()
+                .map(|s| s.to_string())
+        )
+        .is_err());
+    }
 }
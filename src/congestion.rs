@@ -0,0 +1,118 @@
+use crate::WindowStats;
+
+/// CongestionWindow `struct` is used for deciding how many blocks of a
+/// window are sent before waiting for an acknowledgement.
+///
+/// The window starts at a single block and doubles on every acknowledged
+/// window until a loss, after which it grows by one block at a time. Partial
+/// acknowledgements halve the window, and timeouts shrink it back to a single
+/// block. The window never exceeds the negotiated window size.
+#[derive(Debug)]
+pub(crate) struct CongestionWindow {
+    size: u16,
+    threshold: u16,
+    limit: u16,
+    stats: WindowStats,
+}
+
+impl CongestionWindow {
+    /// Creates a new [`CongestionWindow`] limited to the negotiated `limit`.
+    pub(crate) fn new(limit: u16) -> CongestionWindow {
+        CongestionWindow {
+            size: 1,
+            threshold: limit,
+            limit,
+            stats: WindowStats {
+                min: 1,
+                max: 1,
+                last: 1,
+                increases: 0,
+                decreases: 0,
+            },
+        }
+    }
+
+    /// Returns the number of blocks to send.
+    pub(crate) fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// Shrinks the window if not all `sent` blocks were acknowledged, and
+    /// grows it if a whole window was acknowledged.
+    pub(crate) fn acknowledge(&mut self, acknowledged: u16, sent: u16) {
+        if acknowledged < sent {
+            self.threshold = u16::max(self.size / 2, 1);
+            self.resize(self.threshold);
+        } else if sent < self.size {
+            // The end of the file was reached before the window was filled.
+        } else if self.size < self.threshold {
+            self.resize(self.size.saturating_mul(2).min(self.threshold));
+        } else {
+            self.resize(self.size.saturating_add(1));
+        }
+    }
+
+    /// Shrinks the window to a single block after a timeout.
+    pub(crate) fn timeout(&mut self) {
+        self.threshold = u16::max(self.size / 2, 1);
+        self.resize(1);
+    }
+
+    /// Returns how the window has evolved so far.
+    pub(crate) fn stats(&self) -> WindowStats {
+        self.stats
+    }
+
+    fn resize(&mut self, size: u16) {
+        let size = size.clamp(1, self.limit.max(1));
+        if size > self.size {
+            self.stats.increases += 1;
+        } else if size < self.size {
+            self.stats.decreases += 1;
+        }
+
+        self.size = size;
+        self.stats.min = self.stats.min.min(size);
+        self.stats.max = self.stats.max.max(size);
+        self.stats.last = size;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grows_on_acknowledged_windows() {
+        let mut window = CongestionWindow::new(8);
+
+        for expected in [2, 4, 8, 8] {
+            window.acknowledge(window.size(), window.size());
+            assert_eq!(window.size(), expected);
+        }
+        assert_eq!(window.stats().increases, 3);
+        assert_eq!(window.stats().max, 8);
+    }
+
+    #[test]
+    fn shrinks_on_loss() {
+        let mut window = CongestionWindow::new(16);
+        window.acknowledge(1, 1);
+        window.acknowledge(2, 2);
+        window.acknowledge(4, 4);
+
+        window.acknowledge(3, 8);
+        assert_eq!(window.size(), 4);
+        window.acknowledge(4, 4);
+        assert_eq!(window.size(), 5);
+
+        window.timeout();
+        assert_eq!(window.size(), 1);
+        window.acknowledge(1, 1);
+        assert_eq!(window.size(), 2);
+
+        let stats = window.stats();
+        assert_eq!((stats.min, stats.max, stats.last), (1, 8, 2));
+        assert_eq!(stats.decreases, 2);
+    }
+}
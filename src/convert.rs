@@ -1,4 +1,4 @@
-use std::error::Error;
+use crate::TftpError;
 
 /// Allows conversions between byte arrays and other types.
 ///
@@ -17,9 +17,11 @@ pub struct Convert;
 
 impl Convert {
     /// Converts a [`u8`] slice to a [`u16`].
-    pub fn to_u16(buf: &[u8]) -> Result<u16, &'static str> {
+    pub fn to_u16(buf: &[u8]) -> Result<u16, TftpError> {
         if buf.len() < 2 {
-            Err("Error when converting to u16")
+            Err(TftpError::Protocol(
+                "Error when converting to u16".to_string(),
+            ))
         } else {
             Ok(((buf[0] as u16) << 8) + buf[1] as u16)
         }
@@ -27,13 +29,17 @@ impl Convert {
 
     /// Converts a zero-terminated [`u8`] slice to a [`String`], and returns the
     /// size of the [`String`]. Useful for TFTP packet conversions.
-    pub fn to_string(buf: &[u8], start: usize) -> Result<(String, usize), Box<dyn Error>> {
-        match buf[start..].iter().position(|&b| b == 0x00) {
+    pub fn to_string(buf: &[u8], start: usize) -> Result<(String, usize), TftpError> {
+        let Some(rest) = buf.get(start..) else {
+            return Err(TftpError::Protocol("Invalid string".to_string()));
+        };
+
+        match rest.iter().position(|&b| b == 0x00) {
             Some(index) => Ok((
                 String::from_utf8(buf[start..start + index].to_vec())?,
                 index + start,
             )),
-            None => Err("Invalid string".into()),
+            None => Err(TftpError::Protocol("Invalid string".to_string())),
         }
     }
 }
@@ -84,4 +90,10 @@ mod tests {
         assert_eq!(result, "world");
         assert_eq!(index, 11);
     }
+
+    #[test]
+    fn returns_error_on_out_of_bounds_index() {
+        assert!(Convert::to_string(b"hello\0", 6).is_err());
+        assert!(Convert::to_string(b"hello\0", 7).is_err());
+    }
 }
@@ -0,0 +1,102 @@
+use crate::ErrorCode;
+use std::{error::Error, fmt, io, num::ParseIntError, string::FromUtf8Error};
+
+/// TftpError `enum` represents the errors that can occur while handling
+/// TFTP requests and transfers.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::{Packet, TftpError};
+///
+/// match Packet::deserialize(&[0x00, 0x09]) {
+///     Err(TftpError::Protocol(msg)) => assert_eq!(msg, "Invalid opcode"),
+///     _ => panic!("expected a protocol error"),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum TftpError {
+    /// A socket or file operation failed
+    Io(io::Error),
+    /// The remote did not respond in time
+    Timeout,
+    /// A malformed or unexpected packet was received
+    Protocol(String),
+    /// The remote aborted the transfer with an error packet
+    Remote {
+        /// Error code sent by the remote
+        code: ErrorCode,
+        /// Error message sent by the remote
+        msg: String,
+    },
+    /// The requested transfer options could not be negotiated
+    OptionNegotiation(String),
+    /// Access to the requested file was denied
+    AccessDenied(String),
+}
+
+impl fmt::Display for TftpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TftpError::Io(err) => write!(f, "I/O error: {err}"),
+            TftpError::Timeout => write!(f, "Transfer timed out"),
+            TftpError::Protocol(msg) => write!(f, "Protocol violation: {msg}"),
+            TftpError::Remote { code, msg } => write!(f, "Received error code {code}: {msg}"),
+            TftpError::OptionNegotiation(msg) => write!(f, "Option negotiation failed: {msg}"),
+            TftpError::AccessDenied(msg) => write!(f, "Access denied: {msg}"),
+        }
+    }
+}
+
+impl Error for TftpError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TftpError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for TftpError {
+    /// Converts an [`io::Error`] to a [`TftpError`]. Socket read timeouts are
+    /// converted to [`TftpError::Timeout`].
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => TftpError::Timeout,
+            _ => TftpError::Io(err),
+        }
+    }
+}
+
+impl From<FromUtf8Error> for TftpError {
+    fn from(_: FromUtf8Error) -> Self {
+        TftpError::Protocol("Invalid UTF-8 string".to_string())
+    }
+}
+
+impl From<ParseIntError> for TftpError {
+    fn from(_: ParseIntError) -> Self {
+        TftpError::Protocol("Invalid numeric value".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_io_errors() {
+        assert!(matches!(
+            TftpError::from(io::Error::from(io::ErrorKind::WouldBlock)),
+            TftpError::Timeout
+        ));
+        assert!(matches!(
+            TftpError::from(io::Error::from(io::ErrorKind::TimedOut)),
+            TftpError::Timeout
+        ));
+        assert!(matches!(
+            TftpError::from(io::Error::from(io::ErrorKind::NotFound)),
+            TftpError::Io(_)
+        ));
+    }
+}
@@ -9,28 +9,55 @@
 //! - [RFC 2349](https://www.rfc-editor.org/rfc/rfc2349) Timeout Interval Option
 //! - [RFC 2349](https://www.rfc-editor.org/rfc/rfc2349) Transfer Size Option
 //! - [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize Option
+//! - [RFC 2090](https://www.rfc-editor.org/rfc/rfc2090) Multicast Option
+//!
+//! A [`Client`] for downloading files from and uploading files to TFTP servers
+//! is also provided, supporting the same options.
 //!
 //! # Security
 //!
 //! Since TFTP servers do not offer any type of login or access control mechanisms, this server only allows
 //! transfer and receiving inside a chosen folder, and disallows external file access.
 
+mod client;
 mod config;
+mod congestion;
 mod convert;
+mod error;
+mod multicast;
 mod packet;
+mod pktinfo;
+mod report;
 mod server;
+mod shutdown;
 mod socket;
+mod timer;
 mod window;
 mod worker;
 
+pub use client::Client;
+pub use client::ClientConfig;
 pub use config::Config;
 pub use convert::Convert;
+pub use error::TftpError;
 pub use packet::ErrorCode;
+pub use packet::MulticastGroup;
 pub use packet::Opcode;
 pub use packet::OptionType;
 pub use packet::Packet;
+pub use packet::Rollover;
+pub use packet::TransferMode;
 pub use packet::TransferOption;
+pub use report::TransferDirection;
+pub use report::TransferHandle;
+pub use report::TransferReport;
+pub use report::WindowStats;
 pub use server::Server;
+pub use shutdown::ShutdownHandle;
+pub use shutdown::ShutdownSummary;
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use socket::MemorySocket;
 pub use socket::ServerSocket;
 pub use socket::Socket;
 pub use window::Window;
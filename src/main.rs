@@ -7,18 +7,20 @@ fn main() {
         process::exit(1)
     });
 
+    let addresses = config
+        .addresses()
+        .iter()
+        .map(|addr| addr.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
     let mut server = Server::new(&config).unwrap_or_else(|err| {
-        eprintln!(
-            "Problem creating server on {}:{}: {err}",
-            config.ip_address, config.port
-        );
+        eprintln!("Problem creating server on {addresses}: {err}");
         process::exit(1)
     });
 
     println!(
-        "Running TFTP Server on {}:{} in {}",
-        config.ip_address,
-        config.port,
+        "Running TFTP Server on {addresses} in {}",
         config.directory.display()
     );
 
@@ -0,0 +1,411 @@
+use crate::worker::MAX_RETRIES;
+use crate::{MulticastGroup, OptionType, Packet, Rollover, Socket, TftpError, TransferOption};
+use crate::{TransferDirection, TransferHandle, TransferReport};
+use std::{
+    collections::VecDeque,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    net::{SocketAddr, UdpSocket},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// MulticastWorker `struct` is used for sending a file to a group of clients
+/// at once, as described in [RFC 2090](https://www.rfc-editor.org/rfc/rfc2090).
+///
+/// Data packets are sent to the multicast group, and only the master client
+/// acknowledges them. Clients joining the session are queued, and when the
+/// master client has received the whole file, the next client becomes the
+/// master and the blocks it is missing are sent again.
+pub(crate) struct MulticastWorker {
+    socket: UdpSocket,
+    group: SocketAddr,
+    file_name: PathBuf,
+    blk_size: usize,
+    timeout: Duration,
+    options: Vec<TransferOption>,
+    rollover: Rollover,
+    max_retries: u32,
+    clients: Receiver<SocketAddr>,
+    session: MulticastSession,
+}
+
+/// MulticastSession `struct` is used for adding clients to the session of a
+/// running [`MulticastWorker`].
+#[derive(Clone)]
+pub(crate) struct MulticastSession {
+    clients: Sender<SocketAddr>,
+    open: Arc<Mutex<bool>>,
+}
+
+impl MulticastSession {
+    /// Adds the client to the session, and returns `false` if the session
+    /// has finished and will not serve it.
+    pub(crate) fn join(&self, client: SocketAddr) -> bool {
+        // The worker closes the session while holding the lock, after making
+        // sure that no client is left in the channel.
+        match self.open.lock() {
+            Ok(open) if *open => self.clients.send(client).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the session still accepts clients.
+    pub(crate) fn is_open(&self) -> bool {
+        self.open.lock().is_ok_and(|open| *open)
+    }
+
+    fn close(&self) {
+        if let Ok(mut open) = self.open.lock() {
+            *open = false;
+        }
+    }
+}
+
+impl MulticastWorker {
+    /// Creates a new [`MulticastWorker`]. The `options` are acknowledged to
+    /// every client along with the multicast group, and clients join the
+    /// session later through [`MulticastWorker::session()`].
+    pub(crate) fn new(
+        socket: UdpSocket,
+        group: SocketAddr,
+        file_name: PathBuf,
+        blk_size: usize,
+        timeout: Duration,
+        options: Vec<TransferOption>,
+    ) -> Result<MulticastWorker, TftpError> {
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let (sender, clients) = mpsc::channel();
+
+        Ok(MulticastWorker {
+            socket,
+            group,
+            file_name,
+            blk_size,
+            timeout,
+            options,
+            rollover: Rollover::default(),
+            max_retries: MAX_RETRIES,
+            clients,
+            session: MulticastSession {
+                clients: sender,
+                open: Arc::new(Mutex::new(true)),
+            },
+        })
+    }
+
+    /// Sets the block number that block numbers wrap to. (default: 0)
+    pub(crate) fn set_rollover(&mut self, rollover: Rollover) {
+        self.rollover = rollover;
+    }
+
+    /// Sets how many times the worker waits for the master client before
+    /// moving on to the next one. (default: 6)
+    pub(crate) fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Returns the [`MulticastSession`] clients join the session with.
+    pub(crate) fn session(&self) -> MulticastSession {
+        self.session.clone()
+    }
+
+    /// Sends the file to `first` and every client joining the session,
+    /// asynchronously. Returns a [`TransferHandle`] that yields the
+    /// [`TransferReport`] of the session.
+    pub(crate) fn send(self, first: SocketAddr) -> TransferHandle {
+        let file_name = self.file_name.clone();
+
+        let handle = thread::spawn(move || {
+            let mut report =
+                TransferReport::new(self.file_name.clone(), first, TransferDirection::Send);
+            let start = Instant::now();
+
+            if let Err(err) = self.send_file(first, &mut report) {
+                report.error = Some(err);
+            }
+            self.session.close();
+            report.duration = start.elapsed();
+
+            report
+        });
+
+        TransferHandle::new(handle, file_name, first, TransferDirection::Send)
+    }
+
+    fn send_file(&self, first: SocketAddr, report: &mut TransferReport) -> Result<(), TftpError> {
+        let mut file = File::open(&self.file_name)?;
+        let blocks = file.metadata()?.len() / self.blk_size as u64 + 1;
+        let mut sent_blocks = 0;
+        let mut pending = VecDeque::from([first]);
+        let mut completed = 0;
+        let mut last_error = None;
+
+        while let Some(master) = self.next_master(&mut pending) {
+            match self.serve_master(
+                master,
+                &mut file,
+                blocks,
+                &mut pending,
+                &mut sent_blocks,
+                report,
+            ) {
+                Ok(()) => completed += 1,
+                Err(err) => {
+                    report.failed_clients.push(master);
+                    last_error = Some(err);
+                }
+            }
+        }
+
+        match last_error {
+            Some(err) if completed == 0 => Err(err),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends the blocks the master client is missing, until it acknowledges
+    /// the last block of the file.
+    fn serve_master(
+        &self,
+        master: SocketAddr,
+        file: &mut File,
+        blocks: u64,
+        pending: &mut VecDeque<SocketAddr>,
+        sent_blocks: &mut u64,
+        report: &mut TransferReport,
+    ) -> Result<(), TftpError> {
+        // Until the master acknowledges the OACK with the last block it has
+        // received, the next block to send is unknown.
+        let mut next_block: Option<u64> = None;
+        let mut block_size = 0;
+        let mut retry_cnt = 0;
+
+        loop {
+            match next_block {
+                None => self.send_oack(master, true)?,
+                Some(block) => {
+                    block_size = self.send_block(file, block)?;
+                    if block <= *sent_blocks {
+                        report.retransmissions += 1;
+                    }
+                    *sent_blocks = u64::max(*sent_blocks, block);
+                }
+            }
+
+            let deadline = Instant::now() + self.timeout;
+            let acknowledged = loop {
+                self.accept_clients(master, pending)?;
+                if Instant::now() >= deadline {
+                    break None;
+                }
+
+                match Socket::recv_from(&self.socket) {
+                    Ok((Packet::Ack(block_num), from)) if from == master => match next_block {
+                        None => break Some(block_num as u64),
+                        // The master may already have some of the following blocks.
+                        Some(block) => {
+                            let diff = self.rollover.distance(block, block_num);
+                            if diff <= blocks - block {
+                                break Some(block + diff);
+                            }
+                            report.duplicate_acks += 1;
+                        }
+                    },
+                    Ok((Packet::Error { code, msg }, from)) if from == master => {
+                        return Err(TftpError::Remote { code, msg });
+                    }
+                    Ok((Packet::Error { .. }, from)) => pending.retain(|client| *client != from),
+                    Ok(_) | Err(TftpError::Timeout) | Err(TftpError::Protocol(_)) => {}
+                    Err(err) => return Err(err),
+                }
+            };
+
+            match acknowledged {
+                Some(block) => {
+                    if next_block.is_some() {
+                        report.blocks += 1;
+                        report.bytes += block_size as u64;
+                    }
+                    if block >= blocks {
+                        return Ok(());
+                    }
+                    next_block = Some(block + 1);
+                    retry_cnt = 0;
+                }
+                None => {
+                    retry_cnt += 1;
+                    if retry_cnt == self.max_retries {
+                        return Err(TftpError::Timeout);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the next client to become the master, including the clients
+    /// that have joined since the last call. Closes the session if no client
+    /// is left.
+    fn next_master(&self, pending: &mut VecDeque<SocketAddr>) -> Option<SocketAddr> {
+        self.receive_clients(pending);
+        if pending.is_empty() {
+            if let Ok(mut open) = self.session.open.lock() {
+                self.receive_clients(pending);
+                *open = !pending.is_empty();
+            }
+        }
+
+        pending.pop_front()
+    }
+
+    fn receive_clients(&self, pending: &mut VecDeque<SocketAddr>) {
+        while let Ok(client) = self.clients.try_recv() {
+            if !pending.contains(&client) {
+                pending.push_back(client);
+            }
+        }
+    }
+
+    /// Acknowledges the clients that have joined the session. Clients that
+    /// have repeated their request are acknowledged again.
+    fn accept_clients(
+        &self,
+        master: SocketAddr,
+        pending: &mut VecDeque<SocketAddr>,
+    ) -> Result<(), TftpError> {
+        while let Ok(client) = self.clients.try_recv() {
+            if !pending.contains(&client) && client != master {
+                pending.push_back(client);
+            }
+            self.send_oack(client, client == master)?;
+        }
+
+        Ok(())
+    }
+
+    fn send_oack(&self, client: SocketAddr, master: bool) -> Result<(), TftpError> {
+        let mut options = self.options.clone();
+        options.push(TransferOption {
+            option: OptionType::Multicast(Some(MulticastGroup {
+                address: self.group,
+                master,
+            })),
+            value: 0,
+        });
+
+        Socket::send_to(&self.socket, &Packet::Oack(options), &client)
+    }
+
+    fn send_block(&self, file: &mut File, block: u64) -> Result<usize, TftpError> {
+        let mut data = Vec::with_capacity(self.blk_size);
+        file.seek(SeekFrom::Start((block - 1) * self.blk_size as u64))?;
+        file.take(self.blk_size as u64).read_to_end(&mut data)?;
+        let size = data.len();
+
+        let block_num = self.rollover.block_number(block).ok_or_else(|| {
+            TftpError::Protocol("Block number limit exceeded without rollover".to_string())
+        })?;
+        Socket::send_to(&self.socket, &Packet::Data { block_num, data }, &self.group)?;
+
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs};
+
+    #[test]
+    fn closes_session_without_clients() {
+        let file_name = env::temp_dir().join("tftpd_closes_session_without_clients");
+        fs::write(&file_name, [0x01; 10]).unwrap();
+        let worker = MulticastWorker::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            SocketAddr::from(([239, 255, 69, 2], 1758)),
+            file_name.clone(),
+            512,
+            Duration::from_secs(1),
+            Vec::new(),
+        )
+        .unwrap();
+        let session = worker.session();
+        let client = SocketAddr::from(([127, 0, 0, 1], 50000));
+
+        let mut pending = VecDeque::new();
+        assert!(session.join(client));
+        assert_eq!(worker.next_master(&mut pending), Some(client));
+        assert!(session.is_open());
+
+        // A client joining after the last master is never left unserved.
+        assert_eq!(worker.next_master(&mut pending), None);
+        assert!(!session.is_open());
+        assert!(!session.join(client));
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reports_failed_clients() {
+        let file_name = env::temp_dir().join("tftpd_reports_failed_clients");
+        fs::write(&file_name, [0x01; 10]).unwrap();
+        let mut worker = MulticastWorker::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            SocketAddr::from(([239, 255, 69, 3], 1758)),
+            file_name.clone(),
+            512,
+            Duration::from_millis(50),
+            Vec::new(),
+        )
+        .unwrap();
+        worker.set_max_retries(2);
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+
+        let report = worker.send(client.local_addr().unwrap()).join();
+
+        assert!(matches!(report.error, Some(TftpError::Timeout)));
+        assert_eq!(report.failed_clients, [client.local_addr().unwrap()]);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn numbers_blocks_with_rollover() {
+        let file_name = env::temp_dir().join("tftpd_numbers_blocks_with_rollover");
+        fs::write(&file_name, [0x01; 10]).unwrap();
+        let group = UdpSocket::bind("127.0.0.1:0").unwrap();
+        group
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut worker = MulticastWorker::new(
+            UdpSocket::bind("127.0.0.1:0").unwrap(),
+            group.local_addr().unwrap(),
+            file_name.clone(),
+            512,
+            Duration::from_secs(1),
+            Vec::new(),
+        )
+        .unwrap();
+        worker.set_rollover(Rollover::One);
+        let mut file = File::open(&file_name).unwrap();
+
+        worker.send_block(&mut file, 65536).unwrap();
+        assert!(matches!(
+            Socket::recv(&group).unwrap(),
+            Packet::Data { block_num: 1, .. }
+        ));
+
+        worker.set_rollover(Rollover::Disabled);
+        assert!(worker.send_block(&mut file, 65536).is_err());
+
+        fs::remove_file(file_name).unwrap();
+    }
+}
@@ -1,5 +1,9 @@
-use crate::Convert;
-use std::{error::Error, fmt, str::FromStr};
+use crate::{Convert, TftpError};
+use std::{fmt, net::SocketAddr, str::FromStr, time::Duration};
+
+// Block size limits of RFC 2348.
+pub(crate) const MIN_BLOCK_SIZE: usize = 8;
+pub(crate) const MAX_BLOCK_SIZE: usize = 65464;
 
 /// Packet `enum` represents the valid TFTP packet types.
 ///
@@ -22,7 +26,7 @@ pub enum Packet {
         /// Name of the requested file
         filename: String,
         /// Transfer mode
-        mode: String,
+        mode: TransferMode,
         /// Transfer options
         options: Vec<TransferOption>,
     },
@@ -31,7 +35,7 @@ pub enum Packet {
         /// Name of the requested file
         filename: String,
         /// Transfer mode
-        mode: String,
+        mode: TransferMode,
         /// Transfer options
         options: Vec<TransferOption>,
     },
@@ -57,26 +61,63 @@ pub enum Packet {
 
 impl Packet {
     /// Deserializes a [`u8`] slice into a [`Packet`].
-    pub fn deserialize(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
-        let opcode = Opcode::from_u16(Convert::to_u16(&buf[0..=1])?)?;
+    pub fn deserialize(buf: &[u8]) -> Result<Packet, TftpError> {
+        if buf.len() < 2 {
+            return Err(TftpError::Protocol("Packet too short".to_string()));
+        }
+        let opcode = Opcode::from_u16(Convert::to_u16(buf)?)?;
 
         match opcode {
             Opcode::Rrq | Opcode::Wrq => parse_rq(buf, opcode),
             Opcode::Data => parse_data(buf),
             Opcode::Ack => parse_ack(buf),
             Opcode::Error => parse_error(buf),
-            _ => Err("Invalid packet".into()),
+            Opcode::Oack => parse_oack(buf),
         }
     }
 
     /// Serializes a [`Packet`] into a [`Vec<u8>`].
-    pub fn serialize(&self) -> Result<Vec<u8>, &'static str> {
+    pub fn serialize(&self) -> Result<Vec<u8>, TftpError> {
         match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+            } => Ok(serialize_rq(Opcode::Rrq, filename, mode, options)),
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+            } => Ok(serialize_rq(Opcode::Wrq, filename, mode, options)),
             Packet::Data { block_num, data } => Ok(serialize_data(block_num, data)),
             Packet::Ack(block_num) => Ok(serialize_ack(block_num)),
             Packet::Error { code, msg } => Ok(serialize_error(code, msg)),
             Packet::Oack(options) => Ok(serialize_oack(options)),
-            _ => Err("Invalid packet"),
+        }
+    }
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Rrq {
+                filename,
+                mode,
+                options,
+            } => write!(f, "RRQ {filename} {mode}{}", format_options(options)),
+            Packet::Wrq {
+                filename,
+                mode,
+                options,
+            } => write!(f, "WRQ {filename} {mode}{}", format_options(options)),
+            Packet::Data { block_num, data } => {
+                write!(f, "DATA block={block_num} size={}", data.len())
+            }
+            Packet::Ack(block_num) => write!(f, "ACK block={block_num}"),
+            Packet::Error { code, msg } => {
+                write!(f, "ERROR code={} ({code}): {msg}", code.as_u16())
+            }
+            Packet::Oack(options) => write!(f, "OACK{}", format_options(options)),
         }
     }
 }
@@ -113,7 +154,7 @@ pub enum Opcode {
 
 impl Opcode {
     /// Converts a [`u16`] to an [`Opcode`].
-    pub fn from_u16(val: u16) -> Result<Opcode, &'static str> {
+    pub fn from_u16(val: u16) -> Result<Opcode, TftpError> {
         match val {
             0x0001 => Ok(Opcode::Rrq),
             0x0002 => Ok(Opcode::Wrq),
@@ -121,7 +162,7 @@ impl Opcode {
             0x0004 => Ok(Opcode::Ack),
             0x0005 => Ok(Opcode::Error),
             0x0006 => Ok(Opcode::Oack),
-            _ => Err("Invalid opcode"),
+            _ => Err(TftpError::Protocol("Invalid opcode".to_string())),
         }
     }
 
@@ -160,11 +201,21 @@ impl TransferOption {
         [
             self.option.as_str().as_bytes(),
             &[0x00],
-            self.value.to_string().as_bytes(),
+            self.value_string().as_bytes(),
             &[0x00],
         ]
         .concat()
     }
+
+    /// Returns the value of the option as sent in a packet. The value of the
+    /// multicast option is stored in its [`OptionType`].
+    fn value_string(&self) -> String {
+        match self.option {
+            OptionType::Multicast(Some(group)) => group.to_string(),
+            OptionType::Multicast(None) => String::new(),
+            _ => self.value.to_string(),
+        }
+    }
 }
 
 /// OptionType `enum` represents the TFTP option types
@@ -188,8 +239,16 @@ pub enum OptionType {
     TransferSize,
     /// Timeout option type
     Timeout,
+    /// Timeout in milliseconds option type
+    TimeoutMs,
+    /// Timeout in microseconds option type
+    Utimeout,
     /// Windowsize option type
     Windowsize,
+    /// Multicast option type, with the group assigned by the server
+    Multicast(Option<MulticastGroup>),
+    /// Block number rollover option type
+    Rollover,
 }
 
 impl OptionType {
@@ -199,24 +258,256 @@ impl OptionType {
             OptionType::BlockSize => "blksize",
             OptionType::TransferSize => "tsize",
             OptionType::Timeout => "timeout",
+            OptionType::TimeoutMs => "timeoutms",
+            OptionType::Utimeout => "utimeout",
             OptionType::Windowsize => "windowsize",
+            OptionType::Multicast(_) => "multicast",
+            OptionType::Rollover => "rollover",
+        }
+    }
+
+    /// Converts the value of a timeout option to a [`Duration`], or returns
+    /// [`None`] for the other option types.
+    pub(crate) fn timeout(&self, value: usize) -> Option<Duration> {
+        match self {
+            OptionType::Timeout => Some(Duration::from_secs(value as u64)),
+            OptionType::TimeoutMs => Some(Duration::from_millis(value as u64)),
+            OptionType::Utimeout => Some(Duration::from_micros(value as u64)),
+            _ => None,
         }
     }
 }
 
 impl FromStr for OptionType {
-    type Err = &'static str;
+    type Err = TftpError;
 
     /// Converts a [`str`] to an [`OptionType`].
-    fn from_str(value: &str) -> Result<Self, &'static str> {
+    fn from_str(value: &str) -> Result<Self, TftpError> {
         match value {
             "blksize" => Ok(OptionType::BlockSize),
             "tsize" => Ok(OptionType::TransferSize),
             "timeout" => Ok(OptionType::Timeout),
+            "timeoutms" => Ok(OptionType::TimeoutMs),
+            "utimeout" => Ok(OptionType::Utimeout),
             "windowsize" => Ok(OptionType::Windowsize),
-            _ => Err("Invalid option type"),
+            "multicast" => Ok(OptionType::Multicast(None)),
+            "rollover" => Ok(OptionType::Rollover),
+            _ => Err(TftpError::OptionNegotiation(format!(
+                "Invalid option type {value}"
+            ))),
+        }
+    }
+}
+
+/// MulticastGroup `struct` represents the value of the
+/// [RFC 2090](https://www.rfc-editor.org/rfc/rfc2090) multicast option
+/// sent by the server, in the form `addr,port,mc`.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::MulticastGroup;
+///
+/// let group: MulticastGroup = "239.255.0.1,1758,1".parse().unwrap();
+///
+/// assert_eq!(group.address, "239.255.0.1:1758".parse().unwrap());
+/// assert!(group.master);
+/// assert_eq!(group.to_string(), "239.255.0.1,1758,1");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MulticastGroup {
+    /// Address and port of the multicast group
+    pub address: SocketAddr,
+    /// Whether the receiving client is the master client
+    pub master: bool,
+}
+
+impl FromStr for MulticastGroup {
+    type Err = TftpError;
+
+    /// Converts a [`str`] in the form `addr,port,mc` to a [`MulticastGroup`].
+    fn from_str(value: &str) -> Result<Self, TftpError> {
+        let invalid = || TftpError::OptionNegotiation(format!("Invalid multicast value {value}"));
+
+        let mut parts = value.split(',');
+        let (Some(ip), Some(port), Some(master), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+
+        Ok(MulticastGroup {
+            address: SocketAddr::new(
+                ip.parse().map_err(|_| invalid())?,
+                port.parse().map_err(|_| invalid())?,
+            ),
+            master: match master {
+                "0" => false,
+                "1" => true,
+                _ => return Err(invalid()),
+            },
+        })
+    }
+}
+
+impl fmt::Display for MulticastGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{},{},{}",
+            self.address.ip(),
+            self.address.port(),
+            self.master as u8
+        )
+    }
+}
+
+/// Rollover `enum` represents the block number rollover behaviours, used
+/// when a transfer has more blocks than a [`u16`] block number can count.
+///
+/// This `enum` has function implementations for conversion between
+/// [`Rollover`]s and [`str`]s.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::Rollover;
+///
+/// assert_eq!(Rollover::One, "1".parse().unwrap());
+/// assert_eq!(Rollover::Disabled, "none".parse().unwrap());
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Rollover {
+    /// Transfers with more than 65535 blocks are refused
+    Disabled,
+    /// Block numbers wrap from 65535 to 0
+    #[default]
+    Zero,
+    /// Block numbers wrap from 65535 to 1
+    One,
+}
+
+impl Rollover {
+    /// Converts a rollover option value to a [`Rollover`].
+    pub fn from_value(value: usize) -> Option<Rollover> {
+        match value {
+            0 => Some(Rollover::Zero),
+            1 => Some(Rollover::One),
+            _ => None,
+        }
+    }
+
+    /// Converts a [`Rollover`] to its option value, or [`None`] if rollover
+    /// is disabled.
+    pub fn value(self) -> Option<usize> {
+        match self {
+            Rollover::Disabled => None,
+            Rollover::Zero => Some(0),
+            Rollover::One => Some(1),
+        }
+    }
+
+    /// Returns the block number of the block at `index`, counting from the
+    /// first block of the transfer, or [`None`] if rollover is disabled and
+    /// the block number would exceed the limit.
+    pub(crate) fn block_number(self, index: u64) -> Option<u16> {
+        match self {
+            Rollover::Disabled => u16::try_from(index).ok(),
+            Rollover::Zero => Some(index as u16),
+            Rollover::One if index == 0 => Some(0),
+            Rollover::One => Some(((index - 1) % u16::MAX as u64 + 1) as u16),
+        }
+    }
+
+    /// Returns how many blocks `block_num` is ahead of the block at `index`.
+    /// With rollover to 1, block number 0 is never ahead.
+    pub(crate) fn distance(self, index: u64, block_num: u16) -> u64 {
+        match (self, self.block_number(index)) {
+            (Rollover::One, Some(current)) if current != 0 => match block_num {
+                0 => u64::MAX,
+                _ => (block_num as i64 - current as i64).rem_euclid(u16::MAX as i64) as u64,
+            },
+            _ => block_num.wrapping_sub(index as u16) as u64,
+        }
+    }
+}
+
+impl FromStr for Rollover {
+    type Err = TftpError;
+
+    /// Converts a [`str`] to a [`Rollover`].
+    fn from_str(value: &str) -> Result<Self, TftpError> {
+        match value {
+            "none" => Ok(Rollover::Disabled),
+            "0" => Ok(Rollover::Zero),
+            "1" => Ok(Rollover::One),
+            _ => Err(TftpError::OptionNegotiation(format!(
+                "Invalid rollover value {value}"
+            ))),
+        }
+    }
+}
+
+/// TransferMode `enum` represents the TFTP transfer modes.
+///
+/// This `enum` has function implementations for conversion between
+/// [`TransferMode`]s and [`str`]s. Modes are matched case-insensitively,
+/// and unrecognized modes are kept so that they can be reported back.
+///
+/// # Example
+///
+/// ```rust
+/// use tftpd::TransferMode;
+///
+/// assert_eq!(TransferMode::Netascii, TransferMode::from("NetASCII"));
+/// assert_eq!("octet", TransferMode::Octet.as_str());
+/// assert!(!TransferMode::Mail.is_supported());
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub enum TransferMode {
+    /// Netascii transfer mode
+    Netascii,
+    /// Octet transfer mode
+    Octet,
+    /// Mail transfer mode, obsoleted by RFC 1350
+    Mail,
+    /// Unrecognized transfer mode
+    Unknown(String),
+}
+
+impl TransferMode {
+    /// Converts a [`TransferMode`] to a [`str`].
+    pub fn as_str(&self) -> &str {
+        match self {
+            TransferMode::Netascii => "netascii",
+            TransferMode::Octet => "octet",
+            TransferMode::Mail => "mail",
+            TransferMode::Unknown(mode) => mode,
         }
     }
+
+    /// Returns `true` if the [`TransferMode`] is supported by the server.
+    pub fn is_supported(&self) -> bool {
+        matches!(self, TransferMode::Netascii | TransferMode::Octet)
+    }
+}
+
+impl From<&str> for TransferMode {
+    /// Converts a [`str`] to a [`TransferMode`].
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "netascii" => TransferMode::Netascii,
+            "octet" => TransferMode::Octet,
+            "mail" => TransferMode::Mail,
+            _ => TransferMode::Unknown(value.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for TransferMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
 }
 
 /// ErrorCode `enum` represents the error codes used in the TFTP definition.
@@ -229,49 +520,71 @@ impl FromStr for OptionType {
 /// ```rust
 /// use tftpd::ErrorCode;
 ///
-/// assert_eq!(ErrorCode::from_u16(3).unwrap(), ErrorCode::DiskFull);
+/// assert_eq!(ErrorCode::from_u16(3), ErrorCode::DiskFull);
+/// assert_eq!(ErrorCode::from_u16(42), ErrorCode::Unknown(42));
 /// assert_eq!(ErrorCode::FileExists.as_bytes(), [0x00, 0x06]);
 /// ```
-#[repr(u16)]
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ErrorCode {
     /// Not Defined error code
-    NotDefined = 0,
+    NotDefined,
     /// File not found error code
-    FileNotFound = 1,
+    FileNotFound,
     /// Access violation error code
-    AccessViolation = 2,
+    AccessViolation,
     /// Disk full error code
-    DiskFull = 3,
+    DiskFull,
     /// Illegal operation error code
-    IllegalOperation = 4,
+    IllegalOperation,
     /// Unknown ID error code
-    UnknownId = 5,
+    UnknownId,
     /// File exists error code
-    FileExists = 6,
+    FileExists,
     /// No such user error code
-    NoSuchUser = 7,
+    NoSuchUser,
+    /// Option negotiation error code
+    OptionNegotiation,
+    /// Error code not defined by the TFTP definition, with its raw value
+    Unknown(u16),
 }
 
 impl ErrorCode {
-    /// Converts a [`u16`] to an [`ErrorCode`].
-    pub fn from_u16(code: u16) -> Result<ErrorCode, &'static str> {
+    /// Converts a [`u16`] to an [`ErrorCode`]. Codes that are not defined
+    /// are kept as [`ErrorCode::Unknown`].
+    pub fn from_u16(code: u16) -> ErrorCode {
         match code {
-            0 => Ok(ErrorCode::NotDefined),
-            1 => Ok(ErrorCode::FileNotFound),
-            2 => Ok(ErrorCode::AccessViolation),
-            3 => Ok(ErrorCode::DiskFull),
-            4 => Ok(ErrorCode::IllegalOperation),
-            5 => Ok(ErrorCode::UnknownId),
-            6 => Ok(ErrorCode::FileExists),
-            7 => Ok(ErrorCode::NoSuchUser),
-            _ => Err("Invalid error code"),
+            0 => ErrorCode::NotDefined,
+            1 => ErrorCode::FileNotFound,
+            2 => ErrorCode::AccessViolation,
+            3 => ErrorCode::DiskFull,
+            4 => ErrorCode::IllegalOperation,
+            5 => ErrorCode::UnknownId,
+            6 => ErrorCode::FileExists,
+            7 => ErrorCode::NoSuchUser,
+            8 => ErrorCode::OptionNegotiation,
+            _ => ErrorCode::Unknown(code),
+        }
+    }
+
+    /// Converts an [`ErrorCode`] to a [`u16`].
+    pub fn as_u16(self) -> u16 {
+        match self {
+            ErrorCode::NotDefined => 0,
+            ErrorCode::FileNotFound => 1,
+            ErrorCode::AccessViolation => 2,
+            ErrorCode::DiskFull => 3,
+            ErrorCode::IllegalOperation => 4,
+            ErrorCode::UnknownId => 5,
+            ErrorCode::FileExists => 6,
+            ErrorCode::NoSuchUser => 7,
+            ErrorCode::OptionNegotiation => 8,
+            ErrorCode::Unknown(code) => code,
         }
     }
 
     /// Converts an [`ErrorCode`] to a [`u8`] array with 2 elements.
     pub fn as_bytes(self) -> [u8; 2] {
-        (self as u16).to_be_bytes()
+        self.as_u16().to_be_bytes()
     }
 }
 
@@ -286,32 +599,28 @@ impl fmt::Display for ErrorCode {
             ErrorCode::UnknownId => write!(f, "Unknown ID"),
             ErrorCode::FileExists => write!(f, "File Exists"),
             ErrorCode::NoSuchUser => write!(f, "No Such User"),
+            ErrorCode::OptionNegotiation => write!(f, "Option Negotiation"),
+            ErrorCode::Unknown(_) => write!(f, "Unknown"),
         }
     }
 }
 
-fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
-    let mut options = vec![];
+fn format_options(options: &[TransferOption]) -> String {
+    options
+        .iter()
+        .map(|option| format!(" {}={}", option.option.as_str(), option.value_string()))
+        .collect()
+}
+
+fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, TftpError> {
     let filename: String;
     let mode: String;
     let mut zero_index: usize;
 
     (filename, zero_index) = Convert::to_string(buf, 2)?;
     (mode, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-
-    let mut value: String;
-    let mut option;
-    while zero_index < buf.len() - 1 {
-        (option, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
-
-        if let Ok(option) = OptionType::from_str(option.to_lowercase().as_str()) {
-            options.push(TransferOption {
-                option,
-                value: value.parse()?,
-            });
-        }
-    }
+    let mode = TransferMode::from(mode.as_str());
+    let options = parse_options(buf, zero_index + 1)?;
 
     match opcode {
         Opcode::Rrq => Ok(Packet::Rrq {
@@ -324,23 +633,69 @@ fn parse_rq(buf: &[u8], opcode: Opcode) -> Result<Packet, Box<dyn Error>> {
             mode,
             options,
         }),
-        _ => Err("Non request opcode".into()),
+        _ => Err(TftpError::Protocol("Non request opcode".to_string())),
     }
 }
 
-fn parse_data(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
+fn parse_options(buf: &[u8], start: usize) -> Result<Vec<TransferOption>, TftpError> {
+    let mut options = vec![];
+    let mut zero_index = start;
+
+    let mut value: String;
+    let mut option;
+    while zero_index < buf.len() {
+        (option, zero_index) = Convert::to_string(buf, zero_index)?;
+        (value, zero_index) = Convert::to_string(buf, zero_index + 1)?;
+        zero_index += 1;
+
+        match OptionType::from_str(option.to_lowercase().as_str()) {
+            Ok(OptionType::Multicast(_)) if value.is_empty() => options.push(TransferOption {
+                option: OptionType::Multicast(None),
+                value: 0,
+            }),
+            Ok(OptionType::Multicast(_)) => options.push(TransferOption {
+                option: OptionType::Multicast(Some(value.parse()?)),
+                value: 0,
+            }),
+            Ok(option) => options.push(TransferOption {
+                option,
+                value: value.parse()?,
+            }),
+            Err(_) => {}
+        }
+    }
+
+    Ok(options)
+}
+
+fn parse_data(buf: &[u8]) -> Result<Packet, TftpError> {
+    if buf.len() < 4 {
+        return Err(TftpError::Protocol("Data packet too short".to_string()));
+    }
+
     Ok(Packet::Data {
         block_num: Convert::to_u16(&buf[2..])?,
         data: buf[4..].to_vec(),
     })
 }
 
-fn parse_ack(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
+fn parse_ack(buf: &[u8]) -> Result<Packet, TftpError> {
+    if buf.len() != 4 {
+        return Err(TftpError::Protocol(format!(
+            "Invalid acknowledgement packet length {}",
+            buf.len()
+        )));
+    }
+
     Ok(Packet::Ack(Convert::to_u16(&buf[2..])?))
 }
 
-fn parse_error(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
-    let code = ErrorCode::from_u16(Convert::to_u16(&buf[2..])?)?;
+fn parse_error(buf: &[u8]) -> Result<Packet, TftpError> {
+    if buf.len() < 4 {
+        return Err(TftpError::Protocol("Error packet too short".to_string()));
+    }
+
+    let code = ErrorCode::from_u16(Convert::to_u16(&buf[2..])?);
     if let Ok((msg, _)) = Convert::to_string(buf, 4) {
         Ok(Packet::Error { code, msg })
     } else {
@@ -351,6 +706,32 @@ fn parse_error(buf: &[u8]) -> Result<Packet, Box<dyn Error>> {
     }
 }
 
+fn parse_oack(buf: &[u8]) -> Result<Packet, TftpError> {
+    Ok(Packet::Oack(parse_options(buf, 2)?))
+}
+
+fn serialize_rq(
+    opcode: Opcode,
+    filename: &str,
+    mode: &TransferMode,
+    options: &[TransferOption],
+) -> Vec<u8> {
+    let mut buf = [
+        &opcode.as_bytes()[..],
+        filename.as_bytes(),
+        &[0x00],
+        mode.as_str().as_bytes(),
+        &[0x00],
+    ]
+    .concat();
+
+    for option in options {
+        buf = [buf, option.as_bytes()].concat();
+    }
+
+    buf
+}
+
 fn serialize_data(block_num: &u16, data: &Vec<u8>) -> Vec<u8> {
     [
         &Opcode::Data.as_bytes(),
@@ -406,7 +787,7 @@ mod tests {
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
-            assert_eq!(mode, "octet");
+            assert_eq!(mode, TransferMode::Octet);
             assert_eq!(options.len(), 0);
         } else {
             panic!("cannot parse read request")
@@ -443,7 +824,7 @@ mod tests {
         }) = parse_rq(&buf, Opcode::Rrq)
         {
             assert_eq!(filename, "test.png");
-            assert_eq!(mode, "octet");
+            assert_eq!(mode, TransferMode::Octet);
             assert_eq!(options.len(), 3);
             assert_eq!(
                 options[0],
@@ -489,7 +870,7 @@ mod tests {
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
-            assert_eq!(mode, "octet");
+            assert_eq!(mode, TransferMode::Octet);
             assert_eq!(options.len(), 0);
         } else {
             panic!("cannot parse write request")
@@ -522,7 +903,7 @@ mod tests {
         }) = parse_rq(&buf, Opcode::Wrq)
         {
             assert_eq!(filename, "test.png");
-            assert_eq!(mode, "octet");
+            assert_eq!(mode, TransferMode::Octet);
             assert_eq!(options.len(), 2);
             assert_eq!(
                 options[0],
@@ -543,6 +924,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_request_mode_case_insensitively() {
+        let buf = [
+            &Opcode::Rrq.as_bytes()[..],
+            ("test.png".as_bytes()),
+            &[0x00],
+            ("NetAscii".as_bytes()),
+            &[0x00],
+        ]
+        .concat();
+
+        if let Ok(Packet::Rrq { mode, .. }) = parse_rq(&buf, Opcode::Rrq) {
+            assert_eq!(mode, TransferMode::Netascii);
+        } else {
+            panic!("cannot parse read request")
+        }
+    }
+
+    #[test]
+    fn parses_unsupported_request_mode() {
+        let buf = [
+            &Opcode::Wrq.as_bytes()[..],
+            ("test.png".as_bytes()),
+            &[0x00],
+            ("binary".as_bytes()),
+            &[0x00],
+        ]
+        .concat();
+
+        if let Ok(Packet::Wrq { mode, .. }) = parse_rq(&buf, Opcode::Wrq) {
+            assert_eq!(mode, TransferMode::Unknown("binary".to_string()));
+            assert!(!mode.is_supported());
+        } else {
+            panic!("cannot parse write request")
+        }
+    }
+
     #[test]
     fn parses_data() {
         let buf = [
@@ -611,6 +1029,73 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parses_error_with_unknown_code() {
+        let buf = [
+            &Opcode::Error.as_bytes()[..],
+            &[0x00, 0x2A],
+            "custom error".as_bytes(),
+            &[0x00],
+        ]
+        .concat();
+
+        let packet = Packet::deserialize(&buf).unwrap();
+
+        assert_eq!(
+            packet,
+            Packet::Error {
+                code: ErrorCode::Unknown(42),
+                msg: "custom error".to_string()
+            }
+        );
+        assert_eq!(packet.serialize().unwrap(), buf);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packets: [&[u8]; 8] = [
+            &[],
+            &[0x00],
+            &[0x00, 0x01],
+            &[0x00, 0x01, b'a'],
+            &[0x00, 0x01, b'a', 0x00, b'o'],
+            &[0x00, 0x03, 0x00],
+            &[0x00, 0x04, 0x00],
+            &[0x00, 0x05, 0x00],
+        ];
+
+        for buf in packets {
+            assert!(matches!(
+                Packet::deserialize(buf),
+                Err(TftpError::Protocol(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_ack_with_trailing_bytes() {
+        let buf = [&Opcode::Ack.as_bytes()[..], &12u16.to_be_bytes(), &[0x00]].concat();
+
+        assert!(matches!(
+            Packet::deserialize(&buf),
+            Err(TftpError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn deserializes_arbitrary_short_input_without_panicking() {
+        let bytes = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, b'a'];
+
+        for len in 0..=4 {
+            for n in 0..bytes.len().pow(len) {
+                let buf: Vec<u8> = (0..len)
+                    .map(|i| bytes[n / bytes.len().pow(i) % bytes.len()])
+                    .collect();
+                let _ = Packet::deserialize(&buf);
+            }
+        }
+    }
+
     #[test]
     fn serializes_data() {
         let serialized_data = vec![0x00, 0x03, 0x00, 0x10, 0x01, 0x02, 0x03, 0x04];
@@ -659,4 +1144,224 @@ mod tests {
             serialized_oack
         );
     }
+
+    #[test]
+    fn serializes_read_request() {
+        let serialized_rrq = [
+            &Opcode::Rrq.as_bytes()[..],
+            "test.png".as_bytes(),
+            &[0x00],
+            "octet".as_bytes(),
+            &[0x00],
+            "blksize".as_bytes(),
+            &[0x00],
+            "1024".as_bytes(),
+            &[0x00],
+        ]
+        .concat();
+
+        assert_eq!(
+            serialize_rq(
+                Opcode::Rrq,
+                "test.png",
+                &TransferMode::Octet,
+                &[TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1024
+                }]
+            ),
+            serialized_rrq
+        );
+    }
+
+    #[test]
+    fn serializes_write_request() {
+        let serialized_wrq = [
+            &Opcode::Wrq.as_bytes()[..],
+            "test.txt".as_bytes(),
+            &[0x00],
+            "netascii".as_bytes(),
+            &[0x00],
+        ]
+        .concat();
+
+        assert_eq!(
+            serialize_rq(Opcode::Wrq, "test.txt", &TransferMode::Netascii, &[]),
+            serialized_wrq
+        );
+    }
+
+    #[test]
+    fn parses_oack() {
+        let buf = [
+            &Opcode::Oack.as_bytes()[..],
+            "tsize".as_bytes(),
+            &[0x00],
+            "1234".as_bytes(),
+            &[0x00],
+        ]
+        .concat();
+
+        assert_eq!(
+            Packet::deserialize(&buf).unwrap(),
+            Packet::Oack(vec![TransferOption {
+                option: OptionType::TransferSize,
+                value: 1234
+            }])
+        );
+    }
+
+    #[test]
+    fn round_trips_every_packet() {
+        let options = vec![
+            TransferOption {
+                option: OptionType::BlockSize,
+                value: 1432,
+            },
+            TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            },
+            TransferOption {
+                option: OptionType::Timeout,
+                value: 5,
+            },
+            TransferOption {
+                option: OptionType::Windowsize,
+                value: 8,
+            },
+        ];
+        let packets = [
+            Packet::Rrq {
+                filename: "test.png".to_string(),
+                mode: TransferMode::Octet,
+                options: options.clone(),
+            },
+            Packet::Rrq {
+                filename: "test.png".to_string(),
+                mode: TransferMode::Unknown("binary".to_string()),
+                options: vec![],
+            },
+            Packet::Wrq {
+                filename: "dir/test.txt".to_string(),
+                mode: TransferMode::Netascii,
+                options: options.clone(),
+            },
+            Packet::Data {
+                block_num: 65535,
+                data: vec![0x00, 0x01, 0x02],
+            },
+            Packet::Data {
+                block_num: 0,
+                data: vec![],
+            },
+            Packet::Ack(42),
+            Packet::Error {
+                code: ErrorCode::AccessViolation,
+                msg: "file access violation".to_string(),
+            },
+            Packet::Oack(options),
+            Packet::Oack(vec![]),
+        ];
+
+        for packet in packets {
+            assert_eq!(
+                Packet::deserialize(&packet.serialize().unwrap()).unwrap(),
+                packet
+            );
+        }
+    }
+
+    #[test]
+    fn displays_packets() {
+        assert_eq!(
+            Packet::Rrq {
+                filename: "test.png".to_string(),
+                mode: TransferMode::Octet,
+                options: vec![TransferOption {
+                    option: OptionType::BlockSize,
+                    value: 1024
+                }],
+            }
+            .to_string(),
+            "RRQ test.png octet blksize=1024"
+        );
+        assert_eq!(
+            Packet::Data {
+                block_num: 3,
+                data: vec![0x00; 10]
+            }
+            .to_string(),
+            "DATA block=3 size=10"
+        );
+        assert_eq!(Packet::Ack(3).to_string(), "ACK block=3");
+        assert_eq!(
+            Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "disk full".to_string()
+            }
+            .to_string(),
+            "ERROR code=3 (Disk Full): disk full"
+        );
+    }
+
+    #[test]
+    fn parses_multicast_option() {
+        let buf = [
+            &Opcode::Rrq.as_bytes()[..],
+            "kernel".as_bytes(),
+            &[0x00],
+            "octet".as_bytes(),
+            &[0x00],
+            "multicast".as_bytes(),
+            &[0x00, 0x00],
+        ]
+        .concat();
+
+        assert_eq!(
+            Packet::deserialize(&buf).unwrap(),
+            Packet::Rrq {
+                filename: "kernel".to_string(),
+                mode: TransferMode::Octet,
+                options: vec![TransferOption {
+                    option: OptionType::Multicast(None),
+                    value: 0
+                }],
+            }
+        );
+
+        let oack = Packet::Oack(vec![TransferOption {
+            option: OptionType::Multicast(Some(MulticastGroup {
+                address: "239.255.0.1:1758".parse().unwrap(),
+                master: false,
+            })),
+            value: 0,
+        }]);
+        let serialized = oack.serialize().unwrap();
+        assert!(serialized.ends_with(b"multicast\x00239.255.0.1,1758,0\x00"));
+        assert_eq!(Packet::deserialize(&serialized).unwrap(), oack);
+    }
+
+    #[test]
+    fn rejects_invalid_multicast_value() {
+        assert!("239.255.0.1,1758".parse::<MulticastGroup>().is_err());
+        assert!("239.255.0.1,1758,2".parse::<MulticastGroup>().is_err());
+        assert!("group,1758,1".parse::<MulticastGroup>().is_err());
+    }
+
+    #[test]
+    fn rolls_block_numbers_over() {
+        assert_eq!(Rollover::Zero.block_number(65535), Some(65535));
+        assert_eq!(Rollover::Zero.block_number(65536), Some(0));
+        assert_eq!(Rollover::One.block_number(65536), Some(1));
+        assert_eq!(Rollover::One.block_number(131071), Some(1));
+        assert_eq!(Rollover::Disabled.block_number(65535), Some(65535));
+        assert_eq!(Rollover::Disabled.block_number(65536), None);
+
+        assert_eq!(Rollover::Zero.distance(65535, 1), 2);
+        assert_eq!(Rollover::One.distance(65535, 1), 1);
+        assert_eq!(Rollover::One.distance(65536, 65535), 65534);
+        assert_eq!(Rollover::One.distance(65536, 0), u64::MAX);
+        assert_eq!(Rollover::Disabled.distance(10, 12), 2);
+    }
 }
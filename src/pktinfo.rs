@@ -0,0 +1,219 @@
+//! Helpers for learning the local address a datagram was sent to, and for
+//! replying from that same address, using `IP_PKTINFO` and `IPV6_PKTINFO`.
+//!
+//! On platforms without packet information support, the helpers fall back
+//! to plain [`UdpSocket`] calls and never report a local address.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+};
+
+#[cfg(target_os = "linux")]
+use std::{
+    mem,
+    net::{Ipv4Addr, Ipv6Addr},
+    os::fd::AsRawFd,
+    ptr,
+};
+
+#[cfg(target_os = "linux")]
+use socket2::SockAddr;
+
+#[cfg(target_os = "linux")]
+const CONTROL_BUFFER_SIZE: usize = 16;
+
+/// Enables reception of packet information on the socket.
+#[cfg(target_os = "linux")]
+pub(crate) fn enable(socket: &UdpSocket) -> io::Result<()> {
+    let (level, name) = match socket.local_addr()? {
+        SocketAddr::V4(_) => (libc::IPPROTO_IP, libc::IP_PKTINFO),
+        SocketAddr::V6(_) => (libc::IPPROTO_IPV6, libc::IPV6_RECVPKTINFO),
+    };
+    let enable: libc::c_int = 1;
+
+    let result = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &enable as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if result == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Enables reception of packet information on the socket.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn enable(_socket: &UdpSocket) -> io::Result<()> {
+    Ok(())
+}
+
+/// Receives a datagram, returning its size, the remote address and the
+/// local address it was sent to, if known.
+#[cfg(target_os = "linux")]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut control = [0u64; CONTROL_BUFFER_SIZE];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut libc::sockaddr_storage as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    let amt = unsafe { libc::recvmsg(socket.as_raw_fd(), &mut msg, 0) };
+    if amt < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let from = unsafe { SockAddr::new(addr, msg.msg_namelen) }
+        .as_socket()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid remote address"))?;
+
+    let mut local = None;
+    let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+    while !cmsg.is_null() {
+        let header = unsafe { &*cmsg };
+        match (header.cmsg_level, header.cmsg_type) {
+            (libc::IPPROTO_IP, libc::IP_PKTINFO) => {
+                let info = unsafe {
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in_pktinfo)
+                };
+                local = Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    info.ipi_spec_dst.s_addr,
+                ))));
+            }
+            (libc::IPPROTO_IPV6, libc::IPV6_PKTINFO) => {
+                let info = unsafe {
+                    ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const libc::in6_pktinfo)
+                };
+                let ip = Ipv6Addr::from(info.ipi6_addr.s6_addr);
+                if !ip.is_multicast() {
+                    local = Some(IpAddr::V6(ip));
+                }
+            }
+            _ => {}
+        }
+        cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+    }
+
+    Ok((amt as usize, from, local))
+}
+
+/// Receives a datagram, returning its size, the remote address and the
+/// local address it was sent to, if known.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn recv_from(
+    socket: &UdpSocket,
+    buf: &mut [u8],
+) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+    let (amt, from) = socket.recv_from(buf)?;
+
+    Ok((amt, from, None))
+}
+
+/// Sends a datagram to the remote address, using `local` as the source
+/// address if supplied.
+#[cfg(target_os = "linux")]
+pub(crate) fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    to: &SocketAddr,
+    local: Option<IpAddr>,
+) -> io::Result<usize> {
+    let local = match local {
+        Some(local) => local,
+        None => return socket.send_to(buf, to),
+    };
+
+    let addr = SockAddr::from(*to);
+    let mut control = [0u64; CONTROL_BUFFER_SIZE];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = addr.as_ptr() as *mut libc::c_void;
+    msg.msg_namelen = addr.len();
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+
+    unsafe {
+        match local {
+            IpAddr::V4(ip) => {
+                let size = mem::size_of::<libc::in_pktinfo>() as libc::c_uint;
+                msg.msg_controllen = libc::CMSG_SPACE(size) as _;
+
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::IPPROTO_IP;
+                (*cmsg).cmsg_type = libc::IP_PKTINFO;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(size) as _;
+                ptr::write_unaligned(
+                    libc::CMSG_DATA(cmsg) as *mut libc::in_pktinfo,
+                    libc::in_pktinfo {
+                        ipi_ifindex: 0,
+                        ipi_spec_dst: libc::in_addr {
+                            s_addr: u32::from(ip).to_be(),
+                        },
+                        ipi_addr: libc::in_addr { s_addr: 0 },
+                    },
+                );
+            }
+            IpAddr::V6(ip) => {
+                let size = mem::size_of::<libc::in6_pktinfo>() as libc::c_uint;
+                msg.msg_controllen = libc::CMSG_SPACE(size) as _;
+
+                let cmsg = libc::CMSG_FIRSTHDR(&msg);
+                (*cmsg).cmsg_level = libc::IPPROTO_IPV6;
+                (*cmsg).cmsg_type = libc::IPV6_PKTINFO;
+                (*cmsg).cmsg_len = libc::CMSG_LEN(size) as _;
+                ptr::write_unaligned(
+                    libc::CMSG_DATA(cmsg) as *mut libc::in6_pktinfo,
+                    libc::in6_pktinfo {
+                        ipi6_addr: libc::in6_addr {
+                            s6_addr: ip.octets(),
+                        },
+                        ipi6_ifindex: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    let amt = unsafe { libc::sendmsg(socket.as_raw_fd(), &msg, 0) };
+    if amt < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(amt as usize)
+    }
+}
+
+/// Sends a datagram to the remote address, using `local` as the source
+/// address if supplied.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn send_to(
+    socket: &UdpSocket,
+    buf: &[u8],
+    to: &SocketAddr,
+    _local: Option<IpAddr>,
+) -> io::Result<usize> {
+    socket.send_to(buf, to)
+}
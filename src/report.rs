@@ -0,0 +1,139 @@
+use crate::TftpError;
+use std::{fmt, io, net::SocketAddr, path::PathBuf, thread::JoinHandle, time::Duration};
+
+/// TransferDirection `enum` represents the direction of a file transfer,
+/// as seen from the local side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TransferDirection {
+    /// The file is sent to the peer
+    Send,
+    /// The file is received from the peer
+    Receive,
+}
+
+impl fmt::Display for TransferDirection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransferDirection::Send => write!(f, "Send"),
+            TransferDirection::Receive => write!(f, "Receive"),
+        }
+    }
+}
+
+/// TransferReport `struct` describes the outcome of a finished file transfer.
+///
+/// This `struct` is returned by [`TransferHandle::join()`].
+#[derive(Debug)]
+pub struct TransferReport {
+    /// Path of the transferred file
+    pub file: PathBuf,
+    /// Address of the remote peer
+    pub peer: SocketAddr,
+    /// Direction of the transfer
+    pub direction: TransferDirection,
+    /// Number of data bytes transferred, excluding retransmissions
+    pub bytes: u64,
+    /// Number of data blocks transferred, excluding retransmissions
+    pub blocks: u64,
+    /// Number of packets retransmitted by the local side
+    pub retransmissions: u64,
+    /// Number of stale or duplicate acknowledgements ignored by the local side
+    pub duplicate_acks: u64,
+    /// Clients of a multicast session that did not receive the whole file
+    pub failed_clients: Vec<SocketAddr>,
+    /// Evolution of the congestion window, if congestion control was used
+    pub window: Option<WindowStats>,
+    /// Duration of the transfer
+    pub duration: Duration,
+    /// Error that ended the transfer, if it failed
+    pub error: Option<TftpError>,
+    /// Error that kept a partially received file from being removed
+    pub cleanup_error: Option<TftpError>,
+}
+
+impl TransferReport {
+    /// Creates an empty [`TransferReport`] for a transfer that has not started yet.
+    pub fn new(file: PathBuf, peer: SocketAddr, direction: TransferDirection) -> TransferReport {
+        TransferReport {
+            file,
+            peer,
+            direction,
+            bytes: 0,
+            blocks: 0,
+            retransmissions: 0,
+            duplicate_acks: 0,
+            failed_clients: Vec::new(),
+            window: None,
+            duration: Duration::ZERO,
+            error: None,
+            cleanup_error: None,
+        }
+    }
+
+    /// Returns `true` if the transfer finished without an error.
+    pub fn is_success(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// WindowStats `struct` describes how the congestion window of a transfer
+/// evolved, in blocks.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowStats {
+    /// Smallest window used
+    pub min: u16,
+    /// Largest window used
+    pub max: u16,
+    /// Window used at the end of the transfer
+    pub last: u16,
+    /// Number of times the window grew
+    pub increases: u64,
+    /// Number of times the window shrank
+    pub decreases: u64,
+}
+
+/// TransferHandle `struct` is used for waiting on a file transfer that is
+/// running on its own thread.
+///
+/// This `struct` is returned by [`Worker::send()`](crate::Worker::send) and
+/// [`Worker::receive()`](crate::Worker::receive).
+pub struct TransferHandle {
+    handle: JoinHandle<TransferReport>,
+    file: PathBuf,
+    peer: SocketAddr,
+    direction: TransferDirection,
+}
+
+impl TransferHandle {
+    /// Creates a new [`TransferHandle`] from the [`JoinHandle`] of a transfer thread.
+    pub fn new(
+        handle: JoinHandle<TransferReport>,
+        file: PathBuf,
+        peer: SocketAddr,
+        direction: TransferDirection,
+    ) -> TransferHandle {
+        TransferHandle {
+            handle,
+            file,
+            peer,
+            direction,
+        }
+    }
+
+    /// Returns `true` if the transfer has finished, without blocking.
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// Waits for the transfer to finish, and returns its [`TransferReport`].
+    pub fn join(self) -> TransferReport {
+        match self.handle.join() {
+            Ok(report) => report,
+            Err(_) => {
+                let mut report = TransferReport::new(self.file, self.peer, self.direction);
+                report.error = Some(TftpError::Io(io::Error::other("Transfer thread panicked")));
+                report
+            }
+        }
+    }
+}
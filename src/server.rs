@@ -1,19 +1,42 @@
-use crate::{Config, OptionType, ServerSocket, Socket, Worker};
-use crate::{ErrorCode, Packet, TransferOption};
-use std::cmp::max;
+use crate::multicast::{MulticastSession, MulticastWorker};
+use crate::packet::{MAX_BLOCK_SIZE, MIN_BLOCK_SIZE};
+use crate::pktinfo;
+use crate::socket::MAX_REQUEST_PACKET_SIZE;
+use crate::window::netascii_len;
+use crate::{
+    Config, OptionType, Rollover, ServerSocket, ShutdownHandle, ShutdownSummary, Socket, Worker,
+};
+use crate::{
+    ErrorCode, Packet, TftpError, TransferDirection, TransferHandle, TransferMode, TransferOption,
+};
 use std::collections::HashMap;
-use std::error::Error;
-use std::net::{SocketAddr, UdpSocket};
+use std::fs::File;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use socket2::{Domain, Protocol, Type};
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_TIMEOUT_SECS: usize = 255;
 const DEFAULT_BLOCK_SIZE: usize = 512;
 const DEFAULT_WINDOW_SIZE: u16 = 1;
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Creates the [`Socket`] used for communicating with a client.
+type SocketFactory = Box<dyn Fn(&SocketAddr) -> Box<dyn Socket> + Send>;
 
 /// Server `struct` is used for handling incoming TFTP requests.
 ///
+/// A single [`Server`] can listen on multiple addresses, sharing the same
+/// directory, options and clients between all of them.
+///
 /// This `struct` is meant to be created by [`Server::new()`]. See its
 /// documentation for more.
 ///
@@ -28,217 +51,664 @@ const DEFAULT_WINDOW_SIZE: u16 = 1;
 /// let server = Server::new(&config).unwrap();
 /// ```
 pub struct Server {
-    socket: UdpSocket,
+    sockets: Vec<UdpSocket>,
     directory: PathBuf,
     single_port: bool,
-    largest_block_size: usize,
+    largest_block_size: Arc<AtomicUsize>,
     clients: HashMap<SocketAddr, Sender<Packet>>,
+    transfers: Vec<TransferHandle>,
+    shutdown: ShutdownHandle,
+    multicast: Option<SocketAddr>,
+    sessions: HashMap<PathBuf, MulticastSession>,
+    defaults: TransferDefaults,
+    socket_factory: Option<SocketFactory>,
 }
 
 impl Server {
     /// Creates the TFTP Server with the supplied [`Config`].
-    pub fn new(config: &Config) -> Result<Server, Box<dyn Error>> {
-        let socket = UdpSocket::bind(SocketAddr::from((config.ip_address, config.port)))?;
+    pub fn new(config: &Config) -> Result<Server, TftpError> {
+        let sockets = config
+            .addresses()
+            .iter()
+            .map(|addr| {
+                let socket = create_socket(addr, config.dual_stack)?;
+                pktinfo::enable(&socket)?;
+                Ok(socket)
+            })
+            .collect::<Result<Vec<_>, TftpError>>()?;
 
-        let server = Server {
-            socket,
+        Ok(Server::with_sockets(sockets, config))
+    }
+
+    /// Creates a TFTP Server that does not listen, and communicates with
+    /// clients through the sockets created by `socket_factory` instead.
+    /// Multicast sessions are not offered. Only available for fuzzing.
+    #[cfg(feature = "fuzzing")]
+    #[doc(hidden)]
+    pub fn with_socket_factory<F>(config: &Config, socket_factory: F) -> Server
+    where
+        F: Fn(&SocketAddr) -> Box<dyn Socket> + Send + 'static,
+    {
+        Server {
+            multicast: None,
+            socket_factory: Some(Box::new(socket_factory)),
+            ..Server::with_sockets(Vec::new(), config)
+        }
+    }
+
+    fn with_sockets(sockets: Vec<UdpSocket>, config: &Config) -> Server {
+        Server {
+            sockets,
             directory: config.directory.clone(),
             single_port: config.single_port,
-            largest_block_size: DEFAULT_BLOCK_SIZE,
+            largest_block_size: Arc::new(AtomicUsize::new(DEFAULT_BLOCK_SIZE)),
             clients: HashMap::new(),
-        };
+            transfers: Vec::new(),
+            shutdown: ShutdownHandle::new(),
+            multicast: config.multicast,
+            sessions: HashMap::new(),
+            defaults: TransferDefaults {
+                max_block_size: config.max_block_size,
+                rollover: config.rollover,
+                adaptive_timeout: config.adaptive_timeout,
+                min_timeout: config.min_timeout,
+                max_timeout: config.max_timeout,
+                retries: config.retries,
+                congestion_control: config.congestion_control,
+                dally: config.dally,
+            },
+            socket_factory: None,
+        }
+    }
 
-        Ok(server)
+    /// Returns the local [`SocketAddr`]s the server is listening on.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>, TftpError> {
+        Ok(self
+            .sockets
+            .iter()
+            .map(|socket| socket.local_addr())
+            .collect::<Result<_, _>>()?)
     }
 
-    /// Starts listening for connections. Note that this function does not finish running until termination.
-    pub fn listen(&mut self) {
+    /// Returns a [`ShutdownHandle`] that can be used to stop the server.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Handles a single datagram as if it was received from `from` on the
+    /// first listening address. Only available for fuzzing.
+    #[cfg(feature = "fuzzing")]
+    #[doc(hidden)]
+    pub fn handle_datagram(&mut self, buf: &[u8], from: SocketAddr) {
+        if let Ok(packet) = Packet::deserialize(buf) {
+            self.handle_packet(packet, &from, Destination { index: 0, ip: None });
+        }
+    }
+
+    /// Returns the number of transfers, clients and multicast sessions the
+    /// server keeps track of. Only available for fuzzing.
+    #[cfg(feature = "fuzzing")]
+    #[doc(hidden)]
+    pub fn tracked(&self) -> usize {
+        self.transfers.len() + self.clients.len() + self.sessions.len()
+    }
+
+    /// Starts listening for connections on all addresses. Note that this function does not
+    /// finish running until a shutdown is requested through a [`ShutdownHandle`].
+    pub fn listen(&mut self) -> ShutdownSummary {
+        let running = Arc::new(AtomicBool::new(true));
+        let (sender, receiver) = mpsc::channel();
+        for (index, socket) in self.sockets.iter().enumerate() {
+            if let Err(err) = self.spawn_listener(socket, index, sender.clone(), running.clone()) {
+                eprintln!("Could not listen on socket: {err}");
+            }
+        }
+        drop(sender);
+
+        let mut summary = ShutdownSummary::default();
+        let mut deadline = None;
         loop {
-            let received = if self.single_port {
-                self.socket.recv_from_with_size(self.largest_block_size)
-            } else {
-                Socket::recv_from(&self.socket)
-            };
+            self.reap_transfers(&mut summary);
 
-            if let Ok((packet, from)) = received {
-                match packet {
-                    Packet::Rrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        println!("Sending {filename} to {from}");
-                        if let Err(err) = self.handle_rrq(filename.clone(), &mut options, &from) {
-                            eprintln!("Error while sending file: {err}")
-                        }
-                    }
-                    Packet::Wrq {
-                        filename,
-                        mut options,
-                        ..
-                    } => {
-                        println!("Receiving {filename} from {from}");
-                        if let Err(err) = self.handle_wrq(filename.clone(), &mut options, &from) {
-                            eprintln!("Error while receiving file: {err}")
-                        }
-                    }
-                    _ => {
-                        if self.route_packet(packet, &from).is_err() {
-                            if Socket::send_to(
-                                &self.socket,
-                                &Packet::Error {
-                                    code: ErrorCode::IllegalOperation,
-                                    msg: "invalid request".to_string(),
-                                },
-                                &from,
-                            )
-                            .is_err()
-                            {
-                                eprintln!("Could not send error packet");
-                            };
-                            eprintln!("Received invalid request");
-                        }
-                    }
-                };
+            if deadline.is_none() && self.shutdown.is_shutdown() {
+                deadline = Some(Instant::now() + self.shutdown.grace_period());
+            }
+            if let Some(deadline) = deadline {
+                if self.transfers.is_empty() || Instant::now() >= deadline {
+                    break;
+                }
+            }
+
+            match receiver.recv_timeout(POLL_INTERVAL) {
+                Ok((packet, from, dst)) => self.handle_packet(packet, &from, dst),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+        }
+
+        running.store(false, Ordering::Relaxed);
+        summary.abandoned = self.transfers.len();
+        self.transfers.clear();
+
+        summary
+    }
+
+    fn reap_transfers(&mut self, summary: &mut ShutdownSummary) {
+        let (finished, running): (Vec<_>, Vec<_>) = mem::take(&mut self.transfers)
+            .into_iter()
+            .partition(|transfer| transfer.is_finished());
+        self.transfers = running;
+        self.sessions.retain(|_, session| session.is_open());
+
+        for transfer in finished {
+            let report = transfer.join();
+            let file_name = report
+                .file
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy();
+            match (&report.error, report.direction) {
+                (None, TransferDirection::Send) => {
+                    println!("Sent {file_name} to {}", report.peer);
+                    summary.completed += 1;
+                }
+                (None, TransferDirection::Receive) => {
+                    println!("Received {file_name} from {}", report.peer);
+                    summary.completed += 1;
+                }
+                (Some(err), _) => {
+                    eprintln!("{err}");
+                    summary.failed += 1;
+                }
             }
         }
     }
 
+    fn handle_packet(&mut self, packet: Packet, from: &SocketAddr, dst: Destination) {
+        match packet {
+            Packet::Rrq { .. } | Packet::Wrq { .. } if self.shutdown.is_shutdown() => {
+                if self
+                    .reply(
+                        &Packet::Error {
+                            code: ErrorCode::NotDefined,
+                            msg: "server is shutting down".to_string(),
+                        },
+                        from,
+                        dst,
+                    )
+                    .is_err()
+                {
+                    eprintln!("Could not send error packet");
+                };
+            }
+            Packet::Rrq {
+                filename,
+                mode,
+                mut options,
+            } => {
+                println!("Sending {filename} to {from}");
+                if let Err(err) = self.handle_rrq(filename.clone(), &mode, &mut options, from, dst)
+                {
+                    eprintln!("Error while sending file: {err}")
+                }
+            }
+            Packet::Wrq {
+                filename,
+                mode,
+                mut options,
+            } => {
+                println!("Receiving {filename} from {from}");
+                if let Err(err) = self.handle_wrq(filename.clone(), &mode, &mut options, from, dst)
+                {
+                    eprintln!("Error while receiving file: {err}")
+                }
+            }
+            _ => {
+                if self.route_packet(packet, from).is_err() {
+                    if self
+                        .reply(
+                            &Packet::Error {
+                                code: ErrorCode::IllegalOperation,
+                                msg: "invalid request".to_string(),
+                            },
+                            from,
+                            dst,
+                        )
+                        .is_err()
+                    {
+                        eprintln!("Could not send error packet");
+                    };
+                    eprintln!("Received invalid request");
+                }
+            }
+        };
+    }
+
     fn handle_rrq(
         &mut self,
         filename: String,
-        options: &mut [TransferOption],
+        mode: &TransferMode,
+        options: &mut Vec<TransferOption>,
         to: &SocketAddr,
-    ) -> Result<(), Box<dyn Error>> {
+        dst: Destination,
+    ) -> Result<(), TftpError> {
+        if !mode.is_supported() {
+            return self.reject_mode(mode, to, dst);
+        }
+
+        let multicast = take_multicast(options);
         let file_path = &self.directory.join(filename);
+        let netascii = *mode == TransferMode::Netascii;
         match check_file_exists(file_path, &self.directory) {
-            ErrorCode::FileNotFound => Socket::send_to(
-                &self.socket,
+            ErrorCode::FileNotFound => self.reply(
                 &Packet::Error {
                     code: ErrorCode::FileNotFound,
                     msg: "file does not exist".to_string(),
                 },
                 to,
+                dst,
             ),
-            ErrorCode::AccessViolation => Socket::send_to(
-                &self.socket,
-                &Packet::Error {
-                    code: ErrorCode::AccessViolation,
-                    msg: "file access violation".to_string(),
-                },
-                to,
-            ),
-            ErrorCode::FileExists => {
-                let worker_options =
-                    parse_options(options, RequestType::Read(file_path.metadata()?.len()))?;
-                let mut socket: Box<dyn Socket>;
-
-                if self.single_port {
-                    let single_socket = create_single_socket(&self.socket, to)?;
-                    self.clients.insert(*to, single_socket.sender());
-                    self.largest_block_size =
-                        max(self.largest_block_size, worker_options.block_size);
+            ErrorCode::AccessViolation => {
+                self.reply(
+                    &Packet::Error {
+                        code: ErrorCode::AccessViolation,
+                        msg: "file access violation".to_string(),
+                    },
+                    to,
+                    dst,
+                )?;
 
-                    socket = Box::new(single_socket);
+                Err(TftpError::AccessDenied(file_path.display().to_string()))
+            }
+            ErrorCode::FileExists => {
+                // Translating the whole file is only worth it if the client
+                // asks for its size. Otherwise the size on disk is a lower
+                // bound, which is enough to check the block number limit.
+                let transfer_size = options
+                    .iter()
+                    .any(|option| option.option == OptionType::TransferSize);
+                let file_size = if netascii && transfer_size {
+                    netascii_len(&mut File::open(file_path)?)?
                 } else {
-                    socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
+                    file_path.metadata()?.len()
+                };
+
+                if let Some(group) = self.multicast {
+                    if multicast
+                        && !netascii
+                        && !self.single_port
+                        && group.is_ipv4() == to.is_ipv4()
+                    {
+                        return self
+                            .handle_multicast(file_path, options, file_size, group, to, dst);
+                    }
                 }
 
+                let worker_options =
+                    self.negotiate(options, RequestType::Read(file_size), to, dst)?;
+                self.check_block_limit(&worker_options, file_size, to, dst)?;
+                let mut socket = self.create_transfer_socket(&worker_options, to, dst)?;
                 socket.set_read_timeout(worker_options.timeout)?;
                 socket.set_write_timeout(worker_options.timeout)?;
 
-                accept_request(
-                    &socket,
-                    options,
-                    RequestType::Read(file_path.metadata()?.len()),
-                )?;
+                accept_request(&socket, options, RequestType::Read(file_size))?;
+
+                let worker = self.create_worker(socket, file_path, &worker_options, netascii);
+                self.start_transfer(worker, TransferDirection::Send)?;
 
-                let worker = Worker::new(
-                    socket,
-                    file_path.clone(),
-                    worker_options.block_size,
-                    worker_options.timeout,
-                    worker_options.window_size,
-                );
-                worker.send()
+                Ok(())
             }
-            _ => Err("Unexpected error code when checking file".into()),
+            _ => Err(TftpError::Io(io::Error::other(
+                "Unexpected error code when checking file",
+            ))),
         }
     }
 
     fn handle_wrq(
         &mut self,
         file_name: String,
-        options: &mut [TransferOption],
+        mode: &TransferMode,
+        options: &mut Vec<TransferOption>,
         to: &SocketAddr,
-    ) -> Result<(), Box<dyn Error>> {
+        dst: Destination,
+    ) -> Result<(), TftpError> {
+        if !mode.is_supported() {
+            return self.reject_mode(mode, to, dst);
+        }
+
+        take_multicast(options);
         let file_path = &self.directory.join(file_name);
+        let netascii = *mode == TransferMode::Netascii;
         match check_file_exists(file_path, &self.directory) {
-            ErrorCode::FileExists => Socket::send_to(
-                &self.socket,
+            ErrorCode::FileExists => self.reply(
                 &Packet::Error {
                     code: ErrorCode::FileExists,
                     msg: "requested file already exists".to_string(),
                 },
                 to,
+                dst,
             ),
-            ErrorCode::AccessViolation => Socket::send_to(
-                &self.socket,
-                &Packet::Error {
-                    code: ErrorCode::AccessViolation,
-                    msg: "file access violation".to_string(),
-                },
-                to,
-            ),
+            ErrorCode::AccessViolation => {
+                self.reply(
+                    &Packet::Error {
+                        code: ErrorCode::AccessViolation,
+                        msg: "file access violation".to_string(),
+                    },
+                    to,
+                    dst,
+                )?;
+
+                Err(TftpError::AccessDenied(file_path.display().to_string()))
+            }
             ErrorCode::FileNotFound => {
-                let worker_options = parse_options(options, RequestType::Write)?;
-                let mut socket: Box<dyn Socket>;
+                let worker_options = self.negotiate(options, RequestType::Write, to, dst)?;
+                self.check_block_limit(&worker_options, worker_options.transfer_size, to, dst)?;
+                let mut socket = self.create_transfer_socket(&worker_options, to, dst)?;
+                socket.set_read_timeout(worker_options.timeout)?;
+                socket.set_write_timeout(worker_options.timeout)?;
+
+                accept_request(&socket, options, RequestType::Write)?;
+
+                let mut worker = self.create_worker(socket, file_path, &worker_options, netascii);
+                worker.set_acknowledged_options(options.to_vec());
+                self.start_transfer(worker, TransferDirection::Receive)?;
+
+                Ok(())
+            }
+            _ => Err(TftpError::Io(io::Error::other(
+                "Unexpected error code when checking file",
+            ))),
+        }
+    }
 
-                if self.single_port {
-                    let single_socket = create_single_socket(&self.socket, to)?;
-                    self.clients.insert(*to, single_socket.sender());
-                    self.largest_block_size =
-                        max(self.largest_block_size, worker_options.block_size);
+    fn spawn_listener(
+        &self,
+        socket: &UdpSocket,
+        index: usize,
+        sender: Sender<(Packet, SocketAddr, Destination)>,
+        running: Arc<AtomicBool>,
+    ) -> Result<(), TftpError> {
+        let socket = socket.try_clone()?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        let single_port = self.single_port;
+        let largest_block_size = self.largest_block_size.clone();
 
-                    socket = Box::new(single_socket);
+        thread::spawn(move || {
+            while running.load(Ordering::Relaxed) {
+                let size = if single_port {
+                    largest_block_size.load(Ordering::Relaxed)
                 } else {
-                    socket = Box::new(create_multi_socket(&self.socket.local_addr()?, to)?);
+                    MAX_REQUEST_PACKET_SIZE
+                };
+                let mut buf = vec![0; size + 4];
+
+                if let Ok((amt, from, ip)) = pktinfo::recv_from(&socket, &mut buf) {
+                    if let Ok(packet) = Packet::deserialize(&buf[..amt]) {
+                        if sender
+                            .send((packet, from, Destination { index, ip }))
+                            .is_err()
+                        {
+                            break;
+                        }
+                    }
                 }
+            }
+        });
 
-                socket.set_read_timeout(worker_options.timeout)?;
-                socket.set_write_timeout(worker_options.timeout)?;
+        Ok(())
+    }
 
-                accept_request(&socket, options, RequestType::Write)?;
+    /// Adds the client to the multicast session of the file, starting a new
+    /// session if there is none.
+    fn handle_multicast(
+        &mut self,
+        file_path: &Path,
+        options: &mut Vec<TransferOption>,
+        file_size: u64,
+        group: SocketAddr,
+        to: &SocketAddr,
+        dst: Destination,
+    ) -> Result<(), TftpError> {
+        if let Some(session) = self.sessions.get(file_path) {
+            if session.join(*to) {
+                return Ok(());
+            }
+        }
+
+        // Multicast transfers acknowledge every block, and wrap block numbers
+        // like unicast transfers without the rollover option.
+        options.retain(|option| {
+            !matches!(option.option, OptionType::Windowsize | OptionType::Rollover)
+        });
+        let worker_options = self.negotiate(options, RequestType::Read(file_size), to, dst)?;
+        self.check_block_limit(&worker_options, file_size, to, dst)?;
 
-                let worker = Worker::new(
-                    socket,
-                    file_path.clone(),
-                    worker_options.block_size,
-                    worker_options.timeout,
-                    worker_options.window_size,
-                );
-                worker.receive()
+        let local_ip = self.local_ip(dst)?;
+        let socket = create_socket(&SocketAddr::new(local_ip, 0), false)?;
+        if let IpAddr::V4(ip) = local_ip {
+            if !ip.is_unspecified() {
+                socket2::SockRef::from(&socket).set_multicast_if_v4(&ip)?;
             }
-            _ => Err("Unexpected error code when checking file".into()),
         }
+
+        let mut worker = MulticastWorker::new(
+            socket,
+            group,
+            file_path.to_path_buf(),
+            worker_options.block_size,
+            worker_options.timeout,
+            options.to_vec(),
+        )?;
+        worker.set_rollover(worker_options.rollover);
+        worker.set_max_retries(self.defaults.retries);
+        self.sessions
+            .insert(file_path.to_path_buf(), worker.session());
+        self.transfers.push(worker.send(*to));
+
+        Ok(())
+    }
+
+    /// Creates the socket a transfer with `to` is run on, sharing the
+    /// listening socket in single port mode.
+    fn create_transfer_socket(
+        &mut self,
+        worker_options: &WorkerOptions,
+        to: &SocketAddr,
+        dst: Destination,
+    ) -> Result<Box<dyn Socket>, TftpError> {
+        if let Some(socket_factory) = &self.socket_factory {
+            return Ok(socket_factory(to));
+        }
+
+        if self.single_port {
+            let single_socket = create_single_socket(&self.sockets[dst.index], to, dst.ip)?;
+            self.clients.insert(*to, single_socket.sender());
+            self.largest_block_size
+                .fetch_max(worker_options.block_size, Ordering::Relaxed);
+
+            Ok(Box::new(single_socket))
+        } else {
+            Ok(Box::new(create_multi_socket(self.local_ip(dst)?, to)?))
+        }
+    }
+
+    /// Runs the worker on its own thread, keeping track of the transfer.
+    fn start_transfer(
+        &mut self,
+        worker: Worker<dyn Socket>,
+        direction: TransferDirection,
+    ) -> Result<(), TftpError> {
+        let transfer = match direction {
+            TransferDirection::Send => worker.send()?,
+            TransferDirection::Receive => worker.receive()?,
+        };
+        self.transfers.push(transfer);
+
+        Ok(())
+    }
+
+    fn create_worker(
+        &self,
+        socket: Box<dyn Socket>,
+        file_path: &Path,
+        worker_options: &WorkerOptions,
+        netascii: bool,
+    ) -> Worker<dyn Socket> {
+        let mut worker = Worker::new(
+            socket,
+            file_path.to_path_buf(),
+            worker_options.block_size,
+            worker_options.timeout,
+            worker_options.window_size,
+            netascii,
+        );
+        worker.set_rollover(worker_options.rollover);
+        worker.set_max_retries(self.defaults.retries);
+        worker.set_congestion_control(self.defaults.congestion_control);
+        worker.set_dally(self.defaults.dally.unwrap_or(worker_options.timeout));
+        if worker_options.adaptive_timeout {
+            worker.set_adaptive_timeout(self.defaults.min_timeout, self.defaults.max_timeout);
+        }
+
+        worker
+    }
+
+    /// Parses the requested options, replying with an option negotiation
+    /// error if they are invalid.
+    fn negotiate(
+        &self,
+        options: &mut [TransferOption],
+        request_type: RequestType,
+        to: &SocketAddr,
+        dst: Destination,
+    ) -> Result<WorkerOptions, TftpError> {
+        match parse_options(options, request_type, self.defaults) {
+            Err(TftpError::OptionNegotiation(msg)) => {
+                self.reply(
+                    &Packet::Error {
+                        code: ErrorCode::OptionNegotiation,
+                        msg: msg.clone(),
+                    },
+                    to,
+                    dst,
+                )?;
+
+                Err(TftpError::OptionNegotiation(msg))
+            }
+            result => result,
+        }
+    }
+
+    /// Refuses transfers with more blocks than the block numbers can count,
+    /// if block number rollover is disabled.
+    fn check_block_limit(
+        &self,
+        worker_options: &WorkerOptions,
+        size: u64,
+        to: &SocketAddr,
+        dst: Destination,
+    ) -> Result<(), TftpError> {
+        let blocks = size / worker_options.block_size as u64 + 1;
+        if worker_options.rollover != Rollover::Disabled
+            || Rollover::Disabled.block_number(blocks).is_some()
+        {
+            return Ok(());
+        }
+
+        self.reply(
+            &Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "file is too large without block number rollover".to_string(),
+            },
+            to,
+            dst,
+        )?;
+
+        Err(TftpError::Protocol(format!(
+            "Transfer of {size} bytes exceeds the block number limit"
+        )))
+    }
+
+    fn reply(&self, packet: &Packet, to: &SocketAddr, dst: Destination) -> Result<(), TftpError> {
+        if let Some(socket_factory) = &self.socket_factory {
+            return socket_factory(to).send(packet);
+        }
+
+        pktinfo::send_to(&self.sockets[dst.index], &packet.serialize()?, to, dst.ip)?;
+
+        Ok(())
     }
 
-    fn route_packet(&self, packet: Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+    fn reject_mode(
+        &self,
+        mode: &TransferMode,
+        to: &SocketAddr,
+        dst: Destination,
+    ) -> Result<(), TftpError> {
+        self.reply(
+            &Packet::Error {
+                code: ErrorCode::IllegalOperation,
+                msg: format!("unsupported transfer mode: {mode}"),
+            },
+            to,
+            dst,
+        )?;
+
+        Err(TftpError::Protocol(format!(
+            "Unsupported transfer mode: {mode}"
+        )))
+    }
+
+    fn local_ip(&self, dst: Destination) -> Result<IpAddr, TftpError> {
+        match dst.ip {
+            Some(ip) => Ok(ip),
+            None => Ok(self.sockets[dst.index].local_addr()?.ip()),
+        }
+    }
+
+    fn route_packet(&self, packet: Packet, to: &SocketAddr) -> Result<(), TftpError> {
         if self.clients.contains_key(to) {
-            self.clients[to].send(packet)?;
-            Ok(())
+            self.clients[to]
+                .send(packet)
+                .map_err(|_| TftpError::Protocol("Client is no longer active".to_string()))
         } else {
-            Err("No client found for packet".into())
+            Err(TftpError::Protocol(
+                "No client found for packet".to_string(),
+            ))
         }
     }
 }
 
+/// Listening socket a request was received on, and the local address
+/// the request was sent to, if known.
+#[derive(Clone, Copy, Debug)]
+struct Destination {
+    index: usize,
+    ip: Option<IpAddr>,
+}
+
 #[derive(Debug, PartialEq)]
 struct WorkerOptions {
     block_size: usize,
     transfer_size: u64,
     timeout: Duration,
     window_size: u16,
+    rollover: Rollover,
+    adaptive_timeout: bool,
+}
+
+/// Settings of the server that apply to transfers unless the client
+/// negotiates otherwise.
+#[derive(Clone, Copy, Debug)]
+struct TransferDefaults {
+    max_block_size: usize,
+    rollover: Rollover,
+    adaptive_timeout: bool,
+    min_timeout: Duration,
+    max_timeout: Duration,
+    retries: u32,
+    congestion_control: bool,
+    dally: Option<Duration>,
 }
 
 #[derive(Debug, PartialEq)]
@@ -250,12 +720,15 @@ enum RequestType {
 fn parse_options(
     options: &mut [TransferOption],
     request_type: RequestType,
-) -> Result<WorkerOptions, &'static str> {
+    defaults: TransferDefaults,
+) -> Result<WorkerOptions, TftpError> {
     let mut worker_options = WorkerOptions {
         block_size: DEFAULT_BLOCK_SIZE,
         transfer_size: 0,
         timeout: DEFAULT_TIMEOUT,
         window_size: DEFAULT_WINDOW_SIZE,
+        rollover: defaults.rollover,
+        adaptive_timeout: defaults.adaptive_timeout,
     };
 
     for option in options {
@@ -265,7 +738,16 @@ fn parse_options(
         } = option;
 
         match option_type {
-            OptionType::BlockSize => worker_options.block_size = *value,
+            OptionType::BlockSize => {
+                if !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(value) {
+                    return Err(TftpError::OptionNegotiation(
+                        "Invalid blksize value".to_string(),
+                    ));
+                }
+                // Smaller block sizes can be acknowledged, as RFC 2348 permits.
+                *value = usize::min(*value, defaults.max_block_size);
+                worker_options.block_size = *value;
+            }
             OptionType::TransferSize => match request_type {
                 RequestType::Read(size) => {
                     *value = size as usize;
@@ -273,38 +755,90 @@ fn parse_options(
                 }
                 RequestType::Write => worker_options.transfer_size = *value as u64,
             },
-            OptionType::Timeout => {
-                if *value == 0 {
-                    return Err("Invalid timeout value");
+            OptionType::Timeout | OptionType::TimeoutMs | OptionType::Utimeout => {
+                // RFC 2349 allows 1 to 255 seconds, and the finer grained
+                // options are held to the same bounds.
+                let max = match option_type {
+                    OptionType::Timeout => MAX_TIMEOUT_SECS,
+                    OptionType::TimeoutMs => MAX_TIMEOUT_SECS * 1_000,
+                    _ => MAX_TIMEOUT_SECS * 1_000_000,
+                };
+                if !(1..=max).contains(value) {
+                    return Err(TftpError::OptionNegotiation(format!(
+                        "Invalid {} value",
+                        option_type.as_str()
+                    )));
                 }
-                worker_options.timeout = Duration::from_secs(*value as u64);
+                worker_options.timeout = option_type.timeout(*value).unwrap_or(DEFAULT_TIMEOUT);
+                worker_options.adaptive_timeout = false;
             }
             OptionType::Windowsize => {
                 if *value == 0 || *value > u16::MAX as usize {
-                    return Err("Invalid windowsize value");
+                    return Err(TftpError::OptionNegotiation(
+                        "Invalid windowsize value".to_string(),
+                    ));
                 }
                 worker_options.window_size = *value as u16;
             }
+            OptionType::Rollover => {
+                worker_options.rollover = Rollover::from_value(*value).ok_or_else(|| {
+                    TftpError::OptionNegotiation("Invalid rollover value".to_string())
+                })?;
+            }
+            OptionType::Multicast(_) => {}
         }
     }
 
     Ok(worker_options)
 }
 
+/// Removes the multicast option from the options, and returns `true` if
+/// it was requested.
+fn take_multicast(options: &mut Vec<TransferOption>) -> bool {
+    let requested = options
+        .iter()
+        .any(|option| matches!(option.option, OptionType::Multicast(_)));
+    options.retain(|option| !matches!(option.option, OptionType::Multicast(_)));
+
+    requested
+}
+
 fn create_single_socket(
     socket: &UdpSocket,
     remote: &SocketAddr,
-) -> Result<ServerSocket, Box<dyn Error>> {
-    let socket = ServerSocket::new(socket.try_clone()?, *remote);
+    local_ip: Option<IpAddr>,
+) -> Result<ServerSocket, TftpError> {
+    let mut socket = ServerSocket::new(socket.try_clone()?, *remote);
+    if let Some(ip) = local_ip {
+        socket.set_local_ip(ip);
+    }
 
     Ok(socket)
 }
 
-fn create_multi_socket(
-    addr: &SocketAddr,
-    remote: &SocketAddr,
-) -> Result<UdpSocket, Box<dyn Error>> {
-    let socket = UdpSocket::bind(SocketAddr::from((addr.ip(), 0)))?;
+fn create_socket(addr: &SocketAddr, dual_stack: bool) -> Result<UdpSocket, TftpError> {
+    let socket =
+        socket2::Socket::new(Domain::for_address(*addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!dual_stack)?;
+    }
+    socket.bind(&(*addr).into())?;
+
+    Ok(socket.into())
+}
+
+fn create_multi_socket(local_ip: IpAddr, remote: &SocketAddr) -> Result<UdpSocket, TftpError> {
+    let ip = match (local_ip, remote.ip()) {
+        (IpAddr::V4(_), IpAddr::V6(_)) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        (IpAddr::V6(_), IpAddr::V4(_)) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        (ip, _) => ip,
+    };
+    let mapped = match remote.ip() {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().is_some(),
+        IpAddr::V4(_) => false,
+    };
+
+    let socket = create_socket(&SocketAddr::new(ip, 0), mapped)?;
     socket.connect(remote)?;
 
     Ok(socket)
@@ -314,7 +848,7 @@ fn accept_request<T: Socket>(
     socket: &T,
     options: &[TransferOption],
     request_type: RequestType,
-) -> Result<(), Box<dyn Error>> {
+) -> Result<(), TftpError> {
     if !options.is_empty() {
         socket.send(&Packet::Oack(options.to_vec()))?;
         if let RequestType::Read(_) = request_type {
@@ -327,7 +861,7 @@ fn accept_request<T: Socket>(
     Ok(())
 }
 
-fn check_response<T: Socket>(socket: &T) -> Result<(), Box<dyn Error>> {
+fn check_response<T: Socket>(socket: &T) -> Result<(), TftpError> {
     if let Packet::Ack(received_block_number) = socket.recv()? {
         if received_block_number != 0 {
             socket.send(&Packet::Error {
@@ -359,6 +893,20 @@ fn validate_file_path(file: &Path, directory: &PathBuf) -> bool {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::worker::MAX_RETRIES;
+    use crate::{MulticastGroup, Opcode, TransferMode};
+    use std::{env, fs, thread};
+
+    const DEFAULTS: TransferDefaults = TransferDefaults {
+        max_block_size: MAX_BLOCK_SIZE,
+        rollover: Rollover::Zero,
+        adaptive_timeout: true,
+        min_timeout: Duration::from_millis(100),
+        max_timeout: Duration::from_secs(10),
+        retries: MAX_RETRIES,
+        congestion_control: false,
+        dally: None,
+    };
 
     #[test]
     fn validates_file_path() {
@@ -402,7 +950,7 @@ mod tests {
 
         let work_type = RequestType::Read(12341234);
 
-        let worker_options = parse_options(&mut options, work_type).unwrap();
+        let worker_options = parse_options(&mut options, work_type, DEFAULTS).unwrap();
 
         assert_eq!(options[0].value, worker_options.block_size);
         assert_eq!(options[1].value, worker_options.transfer_size as usize);
@@ -424,27 +972,754 @@ mod tests {
                 option: OptionType::Timeout,
                 value: 5,
             },
+            TransferOption {
+                option: OptionType::Rollover,
+                value: 1,
+            },
         ];
 
         let work_type = RequestType::Write;
 
-        let worker_options = parse_options(&mut options, work_type).unwrap();
+        let worker_options = parse_options(&mut options, work_type, DEFAULTS).unwrap();
 
         assert_eq!(options[0].value, worker_options.block_size);
         assert_eq!(options[1].value, worker_options.transfer_size as usize);
         assert_eq!(options[2].value as u64, worker_options.timeout.as_secs());
+        assert_eq!(worker_options.rollover, Rollover::One);
     }
 
     #[test]
     fn parses_default_options() {
         assert_eq!(
-            parse_options(&mut [], RequestType::Write).unwrap(),
+            parse_options(
+                &mut [],
+                RequestType::Write,
+                TransferDefaults {
+                    rollover: Rollover::Disabled,
+                    ..DEFAULTS
+                }
+            )
+            .unwrap(),
             WorkerOptions {
                 block_size: DEFAULT_BLOCK_SIZE,
                 transfer_size: 0,
                 timeout: DEFAULT_TIMEOUT,
                 window_size: DEFAULT_WINDOW_SIZE,
+                rollover: Rollover::Disabled,
+                adaptive_timeout: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parses_fine_grained_timeouts() {
+        let mut options = vec![TransferOption {
+            option: OptionType::TimeoutMs,
+            value: 250,
+        }];
+        let worker_options = parse_options(&mut options, RequestType::Write, DEFAULTS).unwrap();
+        assert_eq!(worker_options.timeout, Duration::from_millis(250));
+
+        let mut options = vec![TransferOption {
+            option: OptionType::Utimeout,
+            value: 500,
+        }];
+        let worker_options = parse_options(&mut options, RequestType::Write, DEFAULTS).unwrap();
+        assert_eq!(worker_options.timeout, Duration::from_micros(500));
+
+        let mut options = vec![TransferOption {
+            option: OptionType::TimeoutMs,
+            value: 255_000,
+        }];
+        let worker_options = parse_options(&mut options, RequestType::Write, DEFAULTS).unwrap();
+        assert_eq!(worker_options.timeout, Duration::from_secs(255));
+
+        for (option, value) in [
+            (OptionType::Timeout, 0),
+            (OptionType::Timeout, 256),
+            (OptionType::TimeoutMs, 0),
+            (OptionType::TimeoutMs, 255_001),
+            (OptionType::Utimeout, 0),
+            (OptionType::Utimeout, 255_000_001),
+        ] {
+            let mut options = vec![TransferOption { option, value }];
+            assert!(matches!(
+                parse_options(&mut options, RequestType::Write, DEFAULTS),
+                Err(TftpError::OptionNegotiation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn negotiates_block_size() {
+        let defaults = TransferDefaults {
+            max_block_size: 1428,
+            ..DEFAULTS
+        };
+        let mut options = vec![TransferOption {
+            option: OptionType::BlockSize,
+            value: 65464,
+        }];
+
+        let worker_options = parse_options(&mut options, RequestType::Write, defaults).unwrap();
+
+        assert_eq!(worker_options.block_size, 1428);
+        assert_eq!(options[0].value, 1428);
+
+        for value in [0, 7, 65465] {
+            let mut options = vec![TransferOption {
+                option: OptionType::BlockSize,
+                value,
+            }];
+            assert!(matches!(
+                parse_options(&mut options, RequestType::Write, defaults),
+                Err(TftpError::OptionNegotiation(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sends_file_over_ipv6() {
+        let directory = initialize("sends_file_over_ipv6");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let server_addr = start_server(&directory, IpAddr::V6(Ipv6Addr::LOCALHOST), false);
+
+        let client = create_client("[::1]:0");
+        client
+            .send_to(&request(Opcode::Rrq, "hello.txt"), server_addr)
+            .unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert!(from.is_ipv6());
+        assert_ne!(from, server_addr);
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 1,
+                data: b"Hello, world!".to_vec()
+            }
+        );
+        Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn receives_file_over_ipv6() {
+        let directory = initialize("receives_file_over_ipv6");
+        let server_addr = start_server(&directory, IpAddr::V6(Ipv6Addr::LOCALHOST), false);
+
+        let client = create_client("[::1]:0");
+        client
+            .send_to(&request(Opcode::Wrq, "hello.txt"), server_addr)
+            .unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert!(from.is_ipv6());
+        assert_eq!(packet, Packet::Ack(0));
+
+        Socket::send_to(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: b"Hello, world!".to_vec(),
+            },
+            &from,
+        )
+        .unwrap();
+
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Ack(1));
+        assert_eq!(
+            fs::read(directory.join("hello.txt")).unwrap(),
+            b"Hello, world!"
+        );
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn repeats_lost_option_acknowledgement() {
+        let directory = initialize("repeats_lost_option_acknowledgement");
+        let server_addr = start_server(&directory, IpAddr::V4(Ipv4Addr::LOCALHOST), false);
+
+        let client = create_client("127.0.0.1:0");
+        let options = vec![TransferOption {
+            option: OptionType::TimeoutMs,
+            value: 100,
+        }];
+        let request = Packet::Wrq {
+            filename: "hello.txt".to_string(),
+            mode: TransferMode::Octet,
+            options: options.clone(),
+        };
+        Socket::send_to(&client, &request, &server_addr).unwrap();
+
+        // The first option acknowledgement is lost, and the client waits for
+        // the server to repeat it instead of repeating the request.
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Oack(options.clone()));
+        let (packet, repeated_from) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Oack(options));
+        assert_eq!(repeated_from, from);
+
+        Socket::send_to(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: b"Hello, world!".to_vec(),
+            },
+            &from,
+        )
+        .unwrap();
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert_eq!(packet, Packet::Ack(1));
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn survives_malformed_datagrams() {
+        let directory = initialize("survives_malformed_datagrams");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let server_addr = start_server(&directory, IpAddr::V4(Ipv4Addr::LOCALHOST), false);
+
+        let client = create_client("127.0.0.1:0");
+        let datagrams: [&[u8]; 6] = [
+            &[],
+            &[0x00],
+            &[0x00, 0x01],
+            &[0x00, 0x01, b'a'],
+            &[0x00, 0x04, 0x00],
+            &[0xff, 0xff, 0xff, 0xff],
+        ];
+        for datagram in datagrams {
+            client.send_to(datagram, server_addr).unwrap();
+        }
+        client
+            .send_to(&request(Opcode::Rrq, "hello.txt"), server_addr)
+            .unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 1,
+                data: b"Hello, world!".to_vec()
             }
         );
+        Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn refuses_large_file_without_rollover() {
+        let directory = initialize("refuses_large_file_without_rollover");
+        fs::write(directory.join("firmware.bin"), vec![0x01; 8 * 65535]).unwrap();
+        let server_addr = run_server(&Config {
+            rollover: Rollover::Disabled,
+            ..config(&directory)
+        });
+
+        let client = create_client("127.0.0.1:0");
+        let request = Packet::Rrq {
+            filename: "firmware.bin".to_string(),
+            mode: TransferMode::Octet,
+            options: vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 8,
+            }],
+        };
+        Socket::send_to(&client, &request, &server_addr).unwrap();
+
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert!(matches!(
+            packet,
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn reports_netascii_transfer_size() {
+        let directory = initialize("reports_netascii_transfer_size");
+        fs::write(directory.join("hello.txt"), b"Hello,\nworld!\n").unwrap();
+        let server_addr = start_server(&directory, IpAddr::V4(Ipv4Addr::LOCALHOST), false);
+
+        let client = create_client("127.0.0.1:0");
+        let request = Packet::Rrq {
+            filename: "hello.txt".to_string(),
+            mode: TransferMode::Netascii,
+            options: vec![TransferOption {
+                option: OptionType::TransferSize,
+                value: 0,
+            }],
+        };
+        Socket::send_to(&client, &request, &server_addr).unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Oack(vec![TransferOption {
+                option: OptionType::TransferSize,
+                value: 16,
+            }])
+        );
+        Socket::send_to(&client, &Packet::Ack(0), &from).unwrap();
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 1,
+                data: b"Hello,\r\nworld!\r\n".to_vec(),
+            }
+        );
+        Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let directory = initialize("rejects_invalid_options");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let server_addr = start_server(&directory, IpAddr::V4(Ipv4Addr::LOCALHOST), false);
+
+        let client = create_client("127.0.0.1:0");
+        let request = Packet::Rrq {
+            filename: "hello.txt".to_string(),
+            mode: TransferMode::Octet,
+            options: vec![TransferOption {
+                option: OptionType::BlockSize,
+                value: 0,
+            }],
+        };
+        Socket::send_to(&client, &request, &server_addr).unwrap();
+
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert!(matches!(
+            packet,
+            Packet::Error {
+                code: ErrorCode::OptionNegotiation,
+                ..
+            }
+        ));
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn sends_file_to_multicast_group() {
+        let directory = initialize("sends_file_to_multicast_group");
+        let contents = (0..1200).map(|i| i as u8).collect::<Vec<_>>();
+        fs::write(directory.join("kernel"), &contents).unwrap();
+        let group = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(239, 255, 69, 1)), free_port());
+        let server_addr = run_server(&Config {
+            multicast: Some(group),
+            ..config(&directory)
+        });
+
+        let first = create_client("127.0.0.1:0");
+        let first_group = join_group(group);
+        let second = create_client("127.0.0.1:0");
+        let second_group = join_group(group);
+
+        first
+            .send_to(&multicast_request("kernel"), server_addr)
+            .unwrap();
+        let (packet, session) = Socket::recv_from(&first).unwrap();
+        assert_eq!(packet, multicast_oack(group, true));
+        Socket::send_to(&first, &Packet::Ack(0), &session).unwrap();
+
+        // The second client joins after the first block.
+        assert_eq!(receive_block(&first_group), (1, contents[..512].to_vec()));
+        receive_block(&second_group);
+        second
+            .send_to(&multicast_request("kernel"), server_addr)
+            .unwrap();
+        assert_eq!(
+            Socket::recv_from(&second).unwrap(),
+            (multicast_oack(group, false), session)
+        );
+
+        for (block, data) in [(2, &contents[512..1024]), (3, &contents[1024..])] {
+            Socket::send_to(&first, &Packet::Ack(block - 1), &session).unwrap();
+            assert_eq!(receive_block(&first_group), (block, data.to_vec()));
+            assert_eq!(receive_block(&second_group), (block, data.to_vec()));
+        }
+        Socket::send_to(&first, &Packet::Ack(3), &session).unwrap();
+
+        // The second client becomes the master, and receives the missing block.
+        assert_eq!(
+            Socket::recv_from(&second).unwrap(),
+            (multicast_oack(group, true), session)
+        );
+        Socket::send_to(&second, &Packet::Ack(0), &session).unwrap();
+        assert_eq!(receive_block(&second_group), (1, contents[..512].to_vec()));
+        Socket::send_to(&second, &Packet::Ack(3), &session).unwrap();
+
+        // A client requesting the file as the session ends is still served,
+        // either by the ending session or by a new one.
+        let third = create_client("127.0.0.1:0");
+        third
+            .send_to(&multicast_request("kernel"), server_addr)
+            .unwrap();
+        let (mut packet, _) = Socket::recv_from(&third).unwrap();
+        if packet == multicast_oack(group, false) {
+            (packet, _) = Socket::recv_from(&third).unwrap();
+        }
+        assert_eq!(packet, multicast_oack(group, true));
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn sends_file_to_ipv4_client_on_dual_stack() {
+        let directory = initialize("sends_file_to_ipv4_client_on_dual_stack");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let server_addr = start_server(&directory, IpAddr::V6(Ipv6Addr::UNSPECIFIED), true);
+
+        let client = create_client("127.0.0.1:0");
+        client
+            .send_to(
+                &request(Opcode::Rrq, "hello.txt"),
+                (Ipv4Addr::LOCALHOST, server_addr.port()),
+            )
+            .unwrap();
+
+        let (packet, from) = Socket::recv_from(&client).unwrap();
+        assert!(from.is_ipv4());
+        assert_eq!(
+            packet,
+            Packet::Data {
+                block_num: 1,
+                data: b"Hello, world!".to_vec()
+            }
+        );
+        Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn sends_files_on_multiple_addresses() {
+        let directory = initialize("sends_files_on_multiple_addresses");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let config = Config {
+            listen_addresses: vec![
+                SocketAddr::from((Ipv4Addr::LOCALHOST, 0)),
+                SocketAddr::from((Ipv6Addr::LOCALHOST, 0)),
+            ],
+            ..config(&directory)
+        };
+
+        let mut server = Server::new(&config).unwrap();
+        let addrs = server.local_addrs().unwrap();
+        assert_eq!(addrs.len(), 3);
+        thread::spawn(move || server.listen());
+
+        for addr in addrs {
+            let client = create_client(if addr.is_ipv4() {
+                "127.0.0.1:0"
+            } else {
+                "[::1]:0"
+            });
+            client
+                .send_to(&request(Opcode::Rrq, "hello.txt"), addr)
+                .unwrap();
+
+            let (packet, from) = Socket::recv_from(&client).unwrap();
+            assert_eq!(from.ip(), addr.ip());
+            assert_eq!(
+                packet,
+                Packet::Data {
+                    block_num: 1,
+                    data: b"Hello, world!".to_vec()
+                }
+            );
+            Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+        }
+
+        clean(&directory);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn replies_from_request_destination_address() {
+        let directory = initialize("replies_from_request_destination_address");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+
+        for single_port in [false, true] {
+            let config = Config {
+                ip_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                single_port,
+                ..config(&directory)
+            };
+            let server_addr = run_server(&config);
+            let destination = SocketAddr::from(([127, 0, 0, 2], server_addr.port()));
+
+            let client = create_client("127.0.0.1:0");
+            client
+                .send_to(&request(Opcode::Rrq, "missing.txt"), destination)
+                .unwrap();
+            let (packet, from) = Socket::recv_from(&client).unwrap();
+            assert_eq!(from, destination);
+            assert!(matches!(
+                packet,
+                Packet::Error {
+                    code: ErrorCode::FileNotFound,
+                    ..
+                }
+            ));
+
+            client
+                .send_to(&request(Opcode::Rrq, "hello.txt"), destination)
+                .unwrap();
+            let (packet, from) = Socket::recv_from(&client).unwrap();
+            assert_eq!(from.ip(), destination.ip());
+            assert_eq!(from.port() == destination.port(), single_port);
+            assert_eq!(
+                packet,
+                Packet::Data {
+                    block_num: 1,
+                    data: b"Hello, world!".to_vec()
+                }
+            );
+            Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+        }
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn waits_for_transfers_on_shutdown() {
+        let directory = initialize("waits_for_transfers_on_shutdown");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let mut server = Server::new(&config(&directory)).unwrap();
+        let server_addr = server.local_addrs().unwrap()[0];
+        let handle = server.shutdown_handle();
+        let listener = thread::spawn(move || server.listen());
+
+        let client = create_client("127.0.0.1:0");
+        client
+            .send_to(&request(Opcode::Rrq, "hello.txt"), server_addr)
+            .unwrap();
+        let (_, from) = Socket::recv_from(&client).unwrap();
+
+        handle.shutdown_with_grace_period(Duration::from_secs(5));
+        client
+            .send_to(&request(Opcode::Rrq, "hello.txt"), server_addr)
+            .unwrap();
+        let (packet, _) = Socket::recv_from(&client).unwrap();
+        assert!(matches!(
+            packet,
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                ..
+            }
+        ));
+
+        Socket::send_to(&client, &Packet::Ack(1), &from).unwrap();
+        assert_eq!(
+            listener.join().unwrap(),
+            ShutdownSummary {
+                completed: 1,
+                failed: 0,
+                abandoned: 0,
+            }
+        );
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn rejects_unsupported_modes() {
+        let directory = initialize("rejects_unsupported_modes");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let mut server = Server::new(&config(&directory)).unwrap();
+        let server_addr = server.local_addrs().unwrap()[0];
+        let handle = server.shutdown_handle();
+        let listener = thread::spawn(move || server.listen());
+
+        let client = create_client("127.0.0.1:0");
+        let requests = [
+            Packet::Rrq {
+                filename: "hello.txt".to_string(),
+                mode: TransferMode::Mail,
+                options: vec![],
+            },
+            Packet::Wrq {
+                filename: "upload.txt".to_string(),
+                mode: TransferMode::Unknown("binary".to_string()),
+                options: vec![],
+            },
+        ];
+        for request in requests {
+            Socket::send_to(&client, &request, &server_addr).unwrap();
+
+            let (packet, from) = Socket::recv_from(&client).unwrap();
+            assert_eq!(from, server_addr);
+            assert!(matches!(
+                packet,
+                Packet::Error {
+                    code: ErrorCode::IllegalOperation,
+                    ..
+                }
+            ));
+        }
+
+        client
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .unwrap();
+        assert!(Socket::recv_from(&client).is_err());
+        assert!(!directory.join("upload.txt").exists());
+
+        handle.shutdown();
+        assert_eq!(listener.join().unwrap(), ShutdownSummary::default());
+
+        clean(&directory);
+    }
+
+    #[test]
+    fn abandons_transfers_after_grace_period() {
+        let directory = initialize("abandons_transfers_after_grace_period");
+        fs::write(directory.join("hello.txt"), b"Hello, world!").unwrap();
+        let mut server = Server::new(&config(&directory)).unwrap();
+        let server_addr = server.local_addrs().unwrap()[0];
+        let handle = server.shutdown_handle();
+        let listener = thread::spawn(move || server.listen());
+
+        let client = create_client("127.0.0.1:0");
+        client
+            .send_to(&request(Opcode::Rrq, "hello.txt"), server_addr)
+            .unwrap();
+        Socket::recv_from(&client).unwrap();
+
+        handle.shutdown_with_grace_period(Duration::from_millis(200));
+        assert_eq!(
+            listener.join().unwrap(),
+            ShutdownSummary {
+                completed: 0,
+                failed: 0,
+                abandoned: 1,
+            }
+        );
+
+        clean(&directory);
+    }
+
+    fn config(directory: &Path) -> Config {
+        Config {
+            ip_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 0,
+            directory: directory.to_path_buf(),
+            ..Config::default()
+        }
+    }
+
+    fn start_server(directory: &Path, ip_address: IpAddr, dual_stack: bool) -> SocketAddr {
+        run_server(&Config {
+            ip_address,
+            dual_stack,
+            ..config(directory)
+        })
+    }
+
+    fn run_server(config: &Config) -> SocketAddr {
+        let mut server = Server::new(config).unwrap();
+        let addr = server.local_addrs().unwrap()[0];
+        thread::spawn(move || server.listen());
+
+        addr
+    }
+
+    fn create_client(addr: &str) -> UdpSocket {
+        let client = UdpSocket::bind(addr).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        client
+    }
+
+    fn multicast_request(filename: &str) -> Vec<u8> {
+        [
+            &request(Opcode::Rrq, filename)[..],
+            OptionType::Multicast(None).as_str().as_bytes(),
+            &[0x00, 0x00],
+        ]
+        .concat()
+    }
+
+    fn multicast_oack(group: SocketAddr, master: bool) -> Packet {
+        Packet::Oack(vec![TransferOption {
+            option: OptionType::Multicast(Some(MulticastGroup {
+                address: group,
+                master,
+            })),
+            value: 0,
+        }])
+    }
+
+    fn join_group(group: SocketAddr) -> UdpSocket {
+        let IpAddr::V4(ip) = group.ip() else {
+            panic!("expected an ipv4 multicast group");
+        };
+        let socket = socket2::Socket::new(Domain::IPV4, Type::DGRAM, None).unwrap();
+        socket.set_reuse_address(true).unwrap();
+        socket
+            .bind(&SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), group.port()).into())
+            .unwrap();
+        socket.join_multicast_v4(&ip, &Ipv4Addr::LOCALHOST).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        socket.into()
+    }
+
+    fn receive_block(socket: &UdpSocket) -> (u16, Vec<u8>) {
+        match Socket::recv_with_size(socket, 512).unwrap() {
+            Packet::Data { block_num, data } => (block_num, data),
+            packet => panic!("expected a data packet, received {packet}"),
+        }
+    }
+
+    fn free_port() -> u16 {
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn request(opcode: Opcode, filename: &str) -> Vec<u8> {
+        [
+            &opcode.as_bytes()[..],
+            filename.as_bytes(),
+            &[0x00],
+            TransferMode::Octet.as_str().as_bytes(),
+            &[0x00],
+        ]
+        .concat()
+    }
+
+    fn initialize(name: &str) -> PathBuf {
+        let directory = env::temp_dir().join(format!("tftpd_{name}"));
+        if directory.exists() {
+            fs::remove_dir_all(&directory).unwrap();
+        }
+        fs::create_dir_all(&directory).unwrap();
+
+        directory
+    }
+
+    fn clean(directory: &Path) {
+        fs::remove_dir_all(directory).unwrap();
     }
 }
@@ -0,0 +1,79 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// ShutdownHandle `struct` is used for stopping a running [`Server`](crate::Server)
+/// from another thread.
+///
+/// The handle is cheap to clone, and requesting a shutdown only stores to
+/// atomic variables, so it is also safe to use from a signal handler.
+///
+/// # Example
+///
+/// ```rust
+/// use std::{thread, time::Duration};
+/// use tftpd::{Config, Server};
+///
+/// let args = ["/", "-p", "0"].iter().map(|s| s.to_string());
+/// let config = Config::new(args).unwrap();
+/// let mut server = Server::new(&config).unwrap();
+///
+/// let handle = server.shutdown_handle();
+/// thread::spawn(move || handle.shutdown_with_grace_period(Duration::from_secs(1)));
+///
+/// let summary = server.listen();
+/// assert_eq!(summary.abandoned, 0);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+    grace_period_ms: Arc<AtomicU64>,
+}
+
+impl ShutdownHandle {
+    /// Creates a new [`ShutdownHandle`] with no shutdown requested.
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// Requests the server to stop accepting new requests and to return
+    /// without waiting for in-flight transfers.
+    pub fn shutdown(&self) {
+        self.shutdown_with_grace_period(Duration::ZERO);
+    }
+
+    /// Requests the server to stop accepting new requests, and to wait up
+    /// to `grace_period` for in-flight transfers to finish before returning.
+    pub fn shutdown_with_grace_period(&self, grace_period: Duration) {
+        self.grace_period_ms
+            .store(grace_period.as_millis() as u64, Ordering::SeqCst);
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns `true` if a shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    /// Returns the period to wait for in-flight transfers after a shutdown
+    /// has been requested.
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_millis(self.grace_period_ms.load(Ordering::SeqCst))
+    }
+}
+
+/// ShutdownSummary `struct` describes the state of the transfers of a
+/// [`Server`](crate::Server) when it stopped listening.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ShutdownSummary {
+    /// Number of transfers that finished successfully while the server was listening
+    pub completed: usize,
+    /// Number of transfers that failed while the server was listening
+    pub failed: usize,
+    /// Number of transfers still running when the server stopped listening
+    pub abandoned: usize,
+}
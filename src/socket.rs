@@ -1,69 +1,69 @@
-use crate::Packet;
+use crate::{pktinfo, Packet, TftpError};
 use std::{
-    error::Error,
-    net::{SocketAddr, UdpSocket},
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
     sync::{
-        mpsc::{self, Receiver, Sender},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
         Mutex,
     },
     time::Duration,
 };
 
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
-const MAX_REQUEST_PACKET_SIZE: usize = 512;
+pub(crate) const MAX_REQUEST_PACKET_SIZE: usize = 512;
 
 /// Socket `trait` is used to allow building custom sockets to be used for
 /// TFTP communication.
 pub trait Socket: Send + Sync + 'static {
     /// Sends a [`Packet`] to the socket's connected remote [`Socket`].
-    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>>;
+    fn send(&self, packet: &Packet) -> Result<(), TftpError>;
     /// Sends a [`Packet`] to the specified remote [`Socket`].
-    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>>;
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError>;
     /// Receives a [`Packet`] from the socket's connected remote [`Socket`]. This
     /// function cannot handle large data packets due to the limited buffer size,
     /// so it is intended for only accepting incoming requests. For handling data
     /// packets, see [`Socket::recv_with_size()`].
-    fn recv(&self) -> Result<Packet, Box<dyn Error>> {
+    fn recv(&self) -> Result<Packet, TftpError> {
         self.recv_with_size(MAX_REQUEST_PACKET_SIZE)
     }
     /// Receives a data packet from the socket's connected remote, and returns the
     /// parsed [`Packet`]. The received packet can actually be of any type, however,
     /// this function also allows supplying the buffer size for an incoming request.
-    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>>;
+    fn recv_with_size(&self, size: usize) -> Result<Packet, TftpError>;
     /// Receives a [`Packet`] from any remote [`Socket`] and returns the [`SocketAddr`]
     /// of the remote [`Socket`]. This function cannot handle large data packets
     /// due to the limited buffer size, so it is intended for only accepting incoming
     /// requests. For handling data packets, see [`Socket::recv_from_with_size()`].
-    fn recv_from(&self) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+    fn recv_from(&self) -> Result<(Packet, SocketAddr), TftpError> {
         self.recv_from_with_size(MAX_REQUEST_PACKET_SIZE)
     }
     /// Receives a data packet from any incoming remote request, and returns the
     /// parsed [`Packet`] and the requesting [`SocketAddr`]. The received packet can
     /// actually be of any type, however, this function also allows supplying the
     /// buffer size for an incoming request.
-    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>>;
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), TftpError>;
     /// Returns the remote [`SocketAddr`] if it exists.
-    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>>;
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError>;
     /// Sets the read timeout for the [`Socket`].
-    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>>;
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError>;
     /// Sets the write timeout for the [`Socket`].
-    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>>;
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError>;
 }
 
 impl Socket for UdpSocket {
-    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+    fn send(&self, packet: &Packet) -> Result<(), TftpError> {
         self.send(&packet.serialize()?)?;
 
         Ok(())
     }
 
-    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError> {
         self.send_to(&packet.serialize()?, to)?;
 
         Ok(())
     }
 
-    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+    fn recv_with_size(&self, size: usize) -> Result<Packet, TftpError> {
         let mut buf = vec![0; size + 4];
         let amt = self.recv(&mut buf)?;
         let packet = Packet::deserialize(&buf[..amt])?;
@@ -71,7 +71,7 @@ impl Socket for UdpSocket {
         Ok(packet)
     }
 
-    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), TftpError> {
         let mut buf = vec![0; size + 4];
         let (amt, addr) = self.recv_from(&mut buf)?;
         let packet = Packet::deserialize(&buf[..amt])?;
@@ -79,17 +79,17 @@ impl Socket for UdpSocket {
         Ok((packet, addr))
     }
 
-    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
         Ok(self.peer_addr()?)
     }
 
-    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         UdpSocket::set_read_timeout(self, Some(dur))?;
 
         Ok(())
     }
 
-    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         UdpSocket::set_write_timeout(self, Some(dur))?;
 
         Ok(())
@@ -116,49 +116,53 @@ impl Socket for UdpSocket {
 pub struct ServerSocket {
     socket: UdpSocket,
     remote: SocketAddr,
+    local_ip: Option<IpAddr>,
     sender: Mutex<Sender<Packet>>,
     receiver: Mutex<Receiver<Packet>>,
     timeout: Duration,
 }
 
 impl Socket for ServerSocket {
-    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+    fn send(&self, packet: &Packet) -> Result<(), TftpError> {
         self.send_to(packet, &self.remote)
     }
 
-    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
-        self.socket.send_to(&packet.serialize()?, to)?;
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError> {
+        pktinfo::send_to(&self.socket, &packet.serialize()?, to, self.local_ip)?;
 
         Ok(())
     }
 
-    fn recv_with_size(&self, _size: usize) -> Result<Packet, Box<dyn Error>> {
+    fn recv_with_size(&self, _size: usize) -> Result<Packet, TftpError> {
         if let Ok(receiver) = self.receiver.lock() {
-            if let Ok(packet) = receiver.recv_timeout(self.timeout) {
-                Ok(packet)
-            } else {
-                Err("Failed to receive".into())
+            match receiver.recv_timeout(self.timeout) {
+                Ok(packet) => Ok(packet),
+                Err(RecvTimeoutError::Timeout) => Err(TftpError::Timeout),
+                Err(RecvTimeoutError::Disconnected) => Err(TftpError::Io(io::Error::new(
+                    io::ErrorKind::NotConnected,
+                    "Failed to receive",
+                ))),
             }
         } else {
-            Err("Failed to lock mutex".into())
+            Err(TftpError::Io(io::Error::other("Failed to lock mutex")))
         }
     }
 
-    fn recv_from_with_size(&self, _size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+    fn recv_from_with_size(&self, _size: usize) -> Result<(Packet, SocketAddr), TftpError> {
         Ok((self.recv()?, self.remote))
     }
 
-    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
         Ok(self.remote)
     }
 
-    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         self.timeout = dur;
 
         Ok(())
     }
 
-    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         self.socket.set_write_timeout(Some(dur))?;
 
         Ok(())
@@ -172,44 +176,120 @@ impl ServerSocket {
         Self {
             socket,
             remote,
+            local_ip: None,
             sender: Mutex::new(sender),
             receiver: Mutex::new(receiver),
             timeout: DEFAULT_TIMEOUT,
         }
     }
 
+    /// Sets the local [`IpAddr`] that [`Packet`]s are sent from. This is useful
+    /// when the underlying [`UdpSocket`] is bound to a wildcard address.
+    pub fn set_local_ip(&mut self, ip: IpAddr) {
+        self.local_ip = Some(ip);
+    }
+
     /// Returns a [`Sender`] for sending [`Packet`]s to the remote [`Socket`].
     pub fn sender(&self) -> Sender<Packet> {
         self.sender.lock().unwrap().clone()
     }
 }
 
+/// MemorySocket `struct` is an in-memory [`Socket`] that records the sent
+/// [`Packet`]s and never receives any. Clones share the recorded packets.
+/// Only available for fuzzing.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+#[derive(Clone)]
+pub struct MemorySocket {
+    remote: SocketAddr,
+    sent: std::sync::Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+#[cfg(feature = "fuzzing")]
+impl MemorySocket {
+    /// Creates a new [`MemorySocket`] connected to `remote`.
+    pub fn new(remote: SocketAddr) -> Self {
+        Self {
+            remote,
+            sent: Default::default(),
+        }
+    }
+
+    /// Returns a [`MemorySocket`] connected to `remote` that shares the
+    /// recorded packets.
+    pub fn connect(&self, remote: SocketAddr) -> Self {
+        Self {
+            remote,
+            sent: self.sent.clone(),
+        }
+    }
+
+    /// Removes and returns the recorded [`Packet`]s, serialized.
+    pub fn take_sent(&self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut *self.sent.lock().unwrap())
+    }
+}
+
+#[cfg(feature = "fuzzing")]
+impl Socket for MemorySocket {
+    fn send(&self, packet: &Packet) -> Result<(), TftpError> {
+        self.send_to(packet, &self.remote)
+    }
+
+    fn send_to(&self, packet: &Packet, _to: &SocketAddr) -> Result<(), TftpError> {
+        self.sent.lock().unwrap().push(packet.serialize()?);
+
+        Ok(())
+    }
+
+    fn recv_with_size(&self, _size: usize) -> Result<Packet, TftpError> {
+        Err(TftpError::Timeout)
+    }
+
+    fn recv_from_with_size(&self, _size: usize) -> Result<(Packet, SocketAddr), TftpError> {
+        Err(TftpError::Timeout)
+    }
+
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
+        Ok(self.remote)
+    }
+
+    fn set_read_timeout(&mut self, _dur: Duration) -> Result<(), TftpError> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&mut self, _dur: Duration) -> Result<(), TftpError> {
+        Ok(())
+    }
+}
+
 impl<T: Socket + ?Sized> Socket for Box<T> {
-    fn send(&self, packet: &Packet) -> Result<(), Box<dyn Error>> {
+    fn send(&self, packet: &Packet) -> Result<(), TftpError> {
         (**self).send(packet)
     }
 
-    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), Box<dyn Error>> {
+    fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError> {
         (**self).send_to(packet, to)
     }
 
-    fn recv_with_size(&self, size: usize) -> Result<Packet, Box<dyn Error>> {
+    fn recv_with_size(&self, size: usize) -> Result<Packet, TftpError> {
         (**self).recv_with_size(size)
     }
 
-    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), Box<dyn Error>> {
+    fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), TftpError> {
         (**self).recv_from_with_size(size)
     }
 
-    fn remote_addr(&self) -> Result<SocketAddr, Box<dyn Error>> {
+    fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
         (**self).remote_addr()
     }
 
-    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         (**self).set_read_timeout(dur)
     }
 
-    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), Box<dyn Error>> {
+    fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
         (**self).set_write_timeout(dur)
     }
 }
@@ -0,0 +1,123 @@
+use std::time::Duration;
+
+/// RetransmissionTimer `struct` is used for deciding when unacknowledged
+/// packets are sent again.
+///
+/// A fixed timer always waits for the same timeout. An adaptive timer
+/// estimates the round trip time from the acknowledgements, as described
+/// in [RFC 6298](https://www.rfc-editor.org/rfc/rfc6298), and doubles its
+/// timeout on every retransmission until a new estimate is made.
+#[derive(Debug)]
+pub(crate) struct RetransmissionTimer {
+    timeout: Duration,
+    estimate: Option<Estimate>,
+    bounds: Option<(Duration, Duration)>,
+}
+
+/// Smoothed round trip time and its variation.
+#[derive(Debug)]
+struct Estimate {
+    srtt: Duration,
+    rttvar: Duration,
+}
+
+impl RetransmissionTimer {
+    /// Creates a [`RetransmissionTimer`] that always waits for `timeout`.
+    pub(crate) fn fixed(timeout: Duration) -> RetransmissionTimer {
+        RetransmissionTimer {
+            timeout,
+            estimate: None,
+            bounds: None,
+        }
+    }
+
+    /// Creates an adaptive [`RetransmissionTimer`] starting at `initial`,
+    /// whose timeout is kept between `min` and `max`.
+    pub(crate) fn adaptive(initial: Duration, min: Duration, max: Duration) -> RetransmissionTimer {
+        RetransmissionTimer {
+            timeout: initial.clamp(min, max),
+            estimate: None,
+            bounds: Some((min, max)),
+        }
+    }
+
+    /// Returns the current retransmission timeout.
+    pub(crate) fn timeout(&self) -> Duration {
+        self.timeout
+    }
+
+    /// Updates the round trip time estimate with a measured `rtt`. Only
+    /// packets that were not retransmitted should be measured.
+    pub(crate) fn sample(&mut self, rtt: Duration) {
+        let Some((min, max)) = self.bounds else {
+            return;
+        };
+
+        let estimate = match self.estimate.take() {
+            None => Estimate {
+                srtt: rtt,
+                rttvar: rtt / 2,
+            },
+            Some(Estimate { srtt, rttvar }) => Estimate {
+                srtt: srtt * 7 / 8 + rtt / 8,
+                rttvar: rttvar * 3 / 4 + srtt.abs_diff(rtt) / 4,
+            },
+        };
+        self.timeout = (estimate.srtt + estimate.rttvar * 4).clamp(min, max);
+        self.estimate = Some(estimate);
+    }
+
+    /// Doubles the retransmission timeout after a loss.
+    pub(crate) fn backoff(&mut self) {
+        if let Some((_, max)) = self.bounds {
+            self.timeout = (self.timeout * 2).min(max);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_fixed_timeout() {
+        let mut timer = RetransmissionTimer::fixed(Duration::from_secs(2));
+
+        timer.sample(Duration::from_millis(10));
+        timer.backoff();
+
+        assert_eq!(timer.timeout(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn estimates_timeout_from_samples() {
+        let mut timer = RetransmissionTimer::adaptive(
+            Duration::from_secs(1),
+            Duration::from_millis(10),
+            Duration::from_secs(10),
+        );
+
+        timer.sample(Duration::from_millis(100));
+        assert_eq!(timer.timeout(), Duration::from_millis(300));
+
+        timer.sample(Duration::from_millis(100));
+        assert_eq!(timer.timeout(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn backs_off_within_bounds() {
+        let mut timer = RetransmissionTimer::adaptive(
+            Duration::from_secs(1),
+            Duration::from_millis(10),
+            Duration::from_secs(3),
+        );
+
+        timer.backoff();
+        assert_eq!(timer.timeout(), Duration::from_secs(2));
+        timer.backoff();
+        assert_eq!(timer.timeout(), Duration::from_secs(3));
+
+        timer.sample(Duration::from_micros(1));
+        assert_eq!(timer.timeout(), Duration::from_millis(10));
+    }
+}
@@ -1,14 +1,20 @@
+use crate::TftpError;
 use std::{
+    cmp::min,
     collections::VecDeque,
-    error::Error,
     fs::File,
     io::{Read, Write},
+    mem,
 };
 
 /// Window `struct` is used to store chunks of data from a file. It is
 /// used to help store the data that is being sent or received for the
 /// [RFC 7440](https://www.rfc-editor.org/rfc/rfc7440) Windowsize option.
 ///
+/// When created in netascii mode, the `Window` translates line endings
+/// between the local file and the network: `LF` is sent as `CR LF` and
+/// `CR` is sent as `CR NUL`, and received data is translated back.
+///
 /// # Example
 /// ```rust
 /// use std::{fs::{self, OpenOptions, File}, io::Write};
@@ -19,7 +25,7 @@ use std::{
 /// file.flush().unwrap();
 ///
 /// let file = File::open("test.txt").unwrap();
-/// let mut window = Window::new(5, 512, file);
+/// let mut window = Window::new(5, 512, file, false);
 /// window.fill().unwrap();
 /// fs::remove_file("test.txt").unwrap();
 /// ```
@@ -28,28 +34,49 @@ pub struct Window {
     size: u16,
     chunk_size: usize,
     file: File,
+    netascii: bool,
+    pending: Vec<u8>,
+    carriage_return: bool,
 }
 
 impl Window {
-    /// Creates a new `Window` with the supplied size and chunk size.
-    pub fn new(size: u16, chunk_size: usize, file: File) -> Window {
+    /// Creates a new `Window` with the supplied size and chunk size. If
+    /// `netascii` is `true`, the data is translated to and from netascii.
+    pub fn new(size: u16, chunk_size: usize, file: File, netascii: bool) -> Window {
         Window {
             elements: VecDeque::new(),
             size,
             chunk_size,
             file,
+            netascii,
+            pending: Vec::new(),
+            carriage_return: false,
         }
     }
 
     /// Fills the `Window` with chunks of data from the file.
     /// Returns `true` if the `Window` is full.
-    pub fn fill(&mut self) -> Result<bool, Box<dyn Error>> {
-        for _ in self.len()..self.size {
-            let mut chunk = vec![0; self.chunk_size];
-            let size = self.file.read(&mut chunk)?;
+    pub fn fill(&mut self) -> Result<bool, TftpError> {
+        // A short chunk is the last one of the file.
+        if self
+            .elements
+            .back()
+            .is_some_and(|chunk| chunk.len() != self.chunk_size)
+        {
+            return Ok(false);
+        }
 
-            if size != self.chunk_size {
+        for _ in self.len()..self.size {
+            let chunk = if self.netascii {
+                self.read_netascii_chunk()?
+            } else {
+                let mut chunk = vec![0; self.chunk_size];
+                let size = self.file.read(&mut chunk)?;
                 chunk.truncate(size);
+                chunk
+            };
+
+            if chunk.len() != self.chunk_size {
                 self.elements.push_back(chunk);
                 return Ok(false);
             }
@@ -61,20 +88,37 @@ impl Window {
     }
 
     /// Empties the `Window` by writing the data to the file.
-    pub fn empty(&mut self) -> Result<(), Box<dyn Error>> {
-        for data in &self.elements {
-            self.file.write_all(data)?;
+    pub fn empty(&mut self) -> Result<(), TftpError> {
+        while let Some(data) = self.elements.pop_front() {
+            let data = if self.netascii {
+                self.decode_netascii(&data)
+            } else {
+                data
+            };
+            self.file.write_all(&data)?;
         }
 
-        self.elements.clear();
+        Ok(())
+    }
+
+    /// Writes any data held back by the netascii translation to the file.
+    /// Should be called once after the final [`Window::empty()`].
+    pub fn flush(&mut self) -> Result<(), TftpError> {
+        if self.carriage_return {
+            self.carriage_return = false;
+            self.file.write_all(b"\r")?;
+        }
+        self.file.flush()?;
 
         Ok(())
     }
 
     /// Removes the first `amount` of elements from the `Window`.
-    pub fn remove(&mut self, amount: u16) -> Result<(), &'static str> {
+    pub fn remove(&mut self, amount: u16) -> Result<(), TftpError> {
         if amount > self.len() {
-            return Err("amount cannot be larger than length of window");
+            return Err(TftpError::Protocol(
+                "amount cannot be larger than length of window".to_string(),
+            ));
         }
 
         drop(self.elements.drain(0..amount as usize));
@@ -83,9 +127,11 @@ impl Window {
     }
 
     /// Adds a data `Vec<u8>` to the `Window`.
-    pub fn add(&mut self, data: Vec<u8>) -> Result<(), &'static str> {
+    pub fn add(&mut self, data: Vec<u8>) -> Result<(), TftpError> {
         if self.len() == self.size {
-            return Err("cannot add to a full window");
+            return Err(TftpError::Protocol(
+                "cannot add to a full window".to_string(),
+            ));
         }
 
         self.elements.push_back(data);
@@ -112,6 +158,78 @@ impl Window {
     pub fn is_full(&self) -> bool {
         self.elements.len() as u16 == self.size
     }
+
+    fn read_netascii_chunk(&mut self) -> Result<Vec<u8>, TftpError> {
+        let mut buf = vec![0; self.chunk_size];
+        while self.pending.len() < self.chunk_size {
+            let size = self.file.read(&mut buf)?;
+            if size == 0 {
+                break;
+            }
+
+            for &byte in &buf[..size] {
+                match byte {
+                    b'\n' => self.pending.extend_from_slice(b"\r\n"),
+                    b'\r' => self.pending.extend_from_slice(b"\r\0"),
+                    _ => self.pending.push(byte),
+                }
+            }
+        }
+
+        let rest = self
+            .pending
+            .split_off(min(self.chunk_size, self.pending.len()));
+
+        Ok(mem::replace(&mut self.pending, rest))
+    }
+
+    fn decode_netascii(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut decoded = Vec::with_capacity(data.len());
+        for &byte in data {
+            if self.carriage_return {
+                self.carriage_return = false;
+                match byte {
+                    b'\n' => {
+                        decoded.push(b'\n');
+                        continue;
+                    }
+                    b'\0' => {
+                        decoded.push(b'\r');
+                        continue;
+                    }
+                    _ => decoded.push(b'\r'),
+                }
+            }
+
+            if byte == b'\r' {
+                self.carriage_return = true;
+            } else {
+                decoded.push(byte);
+            }
+        }
+
+        decoded
+    }
+}
+
+/// Returns the size of the file after it has been translated to netascii.
+pub(crate) fn netascii_len(file: &mut File) -> Result<u64, TftpError> {
+    let mut buf = [0; 4096];
+    let mut len = 0;
+    loop {
+        let size = file.read(&mut buf)?;
+        if size == 0 {
+            break;
+        }
+
+        len += size as u64;
+        len += buf[..size]
+            .iter()
+            .filter(|&&b| b == b'\n' || b == b'\r')
+            .count() as u64;
+    }
+
+    Ok(len)
 }
 
 #[cfg(test)]
@@ -134,7 +252,7 @@ mod tests {
         file.flush().unwrap();
         file.rewind().unwrap();
 
-        let mut window = Window::new(2, 5, file);
+        let mut window = Window::new(2, 5, file, false);
         window.fill().unwrap();
         assert_eq!(window.elements.len(), 2);
         assert_eq!(window.elements[0], b"Hello"[..]);
@@ -149,6 +267,11 @@ mod tests {
         assert_eq!(window.elements[0], b", wor"[..]);
         assert_eq!(window.elements[1], b"ld!"[..]);
 
+        window.remove(1).unwrap();
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements.len(), 1);
+        assert_eq!(window.elements[0], b"ld!"[..]);
+
         clean(FILE_NAME);
     }
 
@@ -158,7 +281,7 @@ mod tests {
 
         let file = initialize(FILE_NAME);
 
-        let mut window = Window::new(3, 5, file);
+        let mut window = Window::new(3, 5, file, false);
         window.add(b"Hello".to_vec()).unwrap();
         assert_eq!(window.elements.len(), 1);
         assert_eq!(window.elements[0], b"Hello"[..]);
@@ -188,6 +311,66 @@ mod tests {
         clean(FILE_NAME);
     }
 
+    #[test]
+    fn fills_window_with_netascii() {
+        const FILE_NAME: &str = "fills_window_with_netascii.txt";
+
+        let mut file = initialize(FILE_NAME);
+        file.write_all(b"ab\ncd\re\n").unwrap();
+        file.flush().unwrap();
+        file.rewind().unwrap();
+
+        let mut window = Window::new(4, 3, file, true);
+        assert!(!window.fill().unwrap());
+        assert_eq!(window.elements.len(), 4);
+        assert_eq!(window.elements[0], b"ab\r"[..]);
+        assert_eq!(window.elements[1], b"\ncd"[..]);
+        assert_eq!(window.elements[2], b"\r\0e"[..]);
+        assert_eq!(window.elements[3], b"\r\n"[..]);
+
+        clean(FILE_NAME);
+    }
+
+    #[test]
+    fn empties_window_with_netascii() {
+        const FILE_NAME: &str = "empties_window_with_netascii.txt";
+
+        let file = initialize(FILE_NAME);
+
+        let mut window = Window::new(2, 3, file, true);
+        window.add(b"ab\r".to_vec()).unwrap();
+        window.add(b"\ncd".to_vec()).unwrap();
+        window.empty().unwrap();
+        window.add(b"\r\0e".to_vec()).unwrap();
+        window.add(b"\r".to_vec()).unwrap();
+        window.empty().unwrap();
+        window.flush().unwrap();
+
+        let mut contents = Default::default();
+        File::read_to_string(
+            &mut File::open(DIR_NAME.to_string() + "/" + FILE_NAME).unwrap(),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, "ab\ncd\re\r");
+
+        clean(FILE_NAME);
+    }
+
+    #[test]
+    fn calculates_netascii_length() {
+        const FILE_NAME: &str = "calculates_netascii_length.txt";
+
+        let mut file = initialize(FILE_NAME);
+        file.write_all(b"ab\ncd\re\n").unwrap();
+        file.flush().unwrap();
+        file.rewind().unwrap();
+
+        assert_eq!(netascii_len(&mut file).unwrap(), 11);
+
+        clean(FILE_NAME);
+    }
+
     fn initialize(file_name: &str) -> File {
         let file_name = DIR_NAME.to_string() + "/" + file_name;
         if !Path::new(DIR_NAME).is_dir() {
@@ -1,14 +1,18 @@
-use crate::{Packet, Socket, Window};
+use crate::congestion::CongestionWindow;
+use crate::timer::RetransmissionTimer;
+use crate::{
+    ErrorCode, Packet, Rollover, Socket, TftpError, TransferDirection, TransferHandle,
+    TransferOption, TransferReport, Window,
+};
 use std::{
-    error::Error,
     fs::{self, File},
+    io,
     path::PathBuf,
     thread,
     time::{Duration, Instant},
 };
 
-const MAX_RETRIES: u32 = 6;
-const TIMEOUT_BUFFER: Duration = Duration::from_secs(1);
+pub(crate) const MAX_RETRIES: u32 = 6;
 
 /// Worker `struct` is used for multithreaded file sending and receiving.
 /// It creates a new socket using the Server's IP and a random port
@@ -32,6 +36,7 @@ const TIMEOUT_BUFFER: Duration = Duration::from_secs(1);
 ///     512,
 ///     Duration::from_secs(1),
 ///     1,
+///     false,
 /// );
 ///
 /// worker.send().unwrap();
@@ -42,16 +47,25 @@ pub struct Worker<T: Socket + ?Sized> {
     blk_size: usize,
     timeout: Duration,
     windowsize: u16,
+    netascii: bool,
+    rollover: Rollover,
+    adaptive_timeout: Option<(Duration, Duration)>,
+    max_retries: u32,
+    congestion_control: bool,
+    dally: Duration,
+    options: Vec<TransferOption>,
 }
 
 impl<T: Socket + ?Sized> Worker<T> {
-    /// Creates a new [`Worker`] with the supplied options.
+    /// Creates a new [`Worker`] with the supplied options. If `netascii` is
+    /// `true`, the file is transferred in netascii mode.
     pub fn new(
         socket: Box<T>,
         file_name: PathBuf,
         blk_size: usize,
         timeout: Duration,
         windowsize: u16,
+        netascii: bool,
     ) -> Worker<T> {
         Worker {
             socket,
@@ -59,110 +73,234 @@ impl<T: Socket + ?Sized> Worker<T> {
             blk_size,
             timeout,
             windowsize,
+            netascii,
+            rollover: Rollover::default(),
+            adaptive_timeout: None,
+            max_retries: MAX_RETRIES,
+            congestion_control: false,
+            dally: Duration::ZERO,
+            options: Vec::new(),
         }
     }
 
+    /// Enables congestion control when sending, which starts with a single
+    /// block and adapts the number of blocks sent at once to the losses,
+    /// without exceeding the window size. The evolution of the window is
+    /// reported in [`TransferReport::window`].
+    ///
+    /// Receivers wait for a whole window before acknowledging it, so the last
+    /// block of a smaller window is sent twice to get it acknowledged.
+    pub fn set_congestion_control(&mut self, congestion_control: bool) {
+        self.congestion_control = congestion_control;
+    }
+
+    /// Sets how long the worker keeps listening after acknowledging the last
+    /// block of a received file, acknowledging it again if the remote repeats
+    /// it, as recommended by [RFC 1350](https://www.rfc-editor.org/rfc/rfc1350).
+    /// (default: no dallying)
+    pub fn set_dally(&mut self, dally: Duration) {
+        self.dally = dally;
+    }
+
+    /// Enables the adaptive retransmission timeout, estimated from the round
+    /// trip times of the transfer and kept between `min` and `max`. The
+    /// supplied timeout is only used until the first estimate.
+    pub fn set_adaptive_timeout(&mut self, min: Duration, max: Duration) {
+        self.adaptive_timeout = Some((min, max));
+    }
+
+    /// Sets how many times the worker waits for the remote before abandoning
+    /// the transfer. (default: 6)
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Sets the options acknowledged to the remote before receiving a file.
+    /// They are acknowledged again if the first block does not arrive in
+    /// time, and block 0 is acknowledged again if there are none.
+    pub fn set_acknowledged_options(&mut self, options: Vec<TransferOption>) {
+        self.options = options;
+    }
+
+    /// Sets the block number [`Rollover`] of the transfer. Block numbers wrap
+    /// to 0 by default.
+    pub fn set_rollover(&mut self, rollover: Rollover) {
+        self.rollover = rollover;
+    }
+
     /// Sends a file to the remote [`SocketAddr`] that has sent a read request using
-    /// a random port, asynchronously.
-    pub fn send(self) -> Result<(), Box<dyn Error>> {
+    /// a random port, asynchronously. Returns a [`TransferHandle`] that yields the
+    /// [`TransferReport`] of the transfer.
+    pub fn send(self) -> Result<TransferHandle, TftpError> {
         let file_name = self.file_name.clone();
-        let remote_addr = self.socket.remote_addr().unwrap();
+        let remote_addr = self.socket.remote_addr()?;
+
+        let handle = thread::spawn(move || {
+            let file_name = self.file_name.clone();
+            let mut report =
+                TransferReport::new(file_name.clone(), remote_addr, TransferDirection::Send);
+            let start = Instant::now();
 
-        thread::spawn(move || {
-            let handle_send = || -> Result<(), Box<dyn Error>> {
-                self.send_file(File::open(&file_name)?)?;
+            let handle_send = || -> Result<(), TftpError> {
+                let file = File::open(&file_name).map_err(|err| self.abort(err.into()))?;
+                self.send_file(file, &mut report)?;
 
                 Ok(())
             };
 
-            match handle_send() {
-                Ok(_) => {
-                    println!(
-                        "Sent {} to {}",
-                        &file_name.file_name().unwrap().to_string_lossy(),
-                        &remote_addr
-                    );
-                }
-                Err(err) => {
-                    eprintln!("{err}");
-                }
+            if let Err(err) = handle_send() {
+                report.error = Some(err);
             }
+            report.duration = start.elapsed();
+
+            report
         });
 
-        Ok(())
+        Ok(TransferHandle::new(
+            handle,
+            file_name,
+            remote_addr,
+            TransferDirection::Send,
+        ))
     }
 
     /// Receives a file from the remote [`SocketAddr`] that has sent a write request using
-    /// the supplied socket, asynchronously.
-    pub fn receive(self) -> Result<(), Box<dyn Error>> {
+    /// the supplied socket, asynchronously. Returns a [`TransferHandle`] that yields the
+    /// [`TransferReport`] of the transfer. The file is created before the transfer
+    /// starts, and an error is returned if it cannot be created.
+    pub fn receive(self) -> Result<TransferHandle, TftpError> {
         let file_name = self.file_name.clone();
-        let remote_addr = self.socket.remote_addr().unwrap();
+        let remote_addr = self.socket.remote_addr()?;
+        let file = File::create(&file_name).map_err(|err| self.abort(err.into()))?;
 
-        thread::spawn(move || {
-            let handle_receive = || -> Result<(), Box<dyn Error>> {
-                self.receive_file(File::create(&file_name)?)?;
-
-                Ok(())
-            };
+        let handle = thread::spawn(move || {
+            let file_name = self.file_name.clone();
+            let mut report =
+                TransferReport::new(file_name.clone(), remote_addr, TransferDirection::Receive);
+            let start = Instant::now();
 
-            match handle_receive() {
-                Ok(_) => {
-                    println!(
-                        "Received {} from {}",
-                        &file_name.file_name().unwrap().to_string_lossy(),
-                        remote_addr
-                    );
-                }
-                Err(err) => {
-                    eprintln!("{err}");
-                    if fs::remove_file(&file_name).is_err() {
-                        eprintln!("Error while cleaning {}", &file_name.to_str().unwrap());
-                    }
+            if let Err(err) = self.receive_file(file, &mut report) {
+                report.error = Some(err);
+                if let Err(err) = fs::remove_file(&file_name) {
+                    report.cleanup_error = Some(err.into());
                 }
             }
+            report.duration = start.elapsed();
+
+            report
         });
 
-        Ok(())
+        Ok(TransferHandle::new(
+            handle,
+            file_name,
+            remote_addr,
+            TransferDirection::Receive,
+        ))
     }
 
-    fn send_file(self, file: File) -> Result<(), Box<dyn Error>> {
-        let mut block_number = 1;
-        let mut window = Window::new(self.windowsize, self.blk_size, file);
+    fn send_file(mut self, file: File, report: &mut TransferReport) -> Result<(), TftpError> {
+        let mut block_index = 1;
+        let mut window = Window::new(self.windowsize, self.blk_size, file, self.netascii);
+        let mut sent_blocks = 0;
+        let mut timer = match self.adaptive_timeout {
+            Some((min, max)) => RetransmissionTimer::adaptive(self.timeout, min, max),
+            None => RetransmissionTimer::fixed(self.timeout),
+        };
+        self.socket.set_read_timeout(timer.timeout())?;
+        let mut congestion = self
+            .congestion_control
+            .then(|| CongestionWindow::new(self.windowsize));
 
         loop {
-            let filled = window.fill()?;
+            let filled = window.fill().map_err(|err| self.abort(err))?;
 
             let mut retry_cnt = 0;
-            let mut time = Instant::now() - (self.timeout + TIMEOUT_BUFFER);
+            let mut sent_at: Option<Instant> = None;
+            let mut retransmitted = false;
+            let mut sent = 0;
             loop {
-                if time.elapsed() >= self.timeout {
-                    send_window(&self.socket, &window, block_number)?;
-                    time = Instant::now();
+                if sent_at.is_none_or(|sent_at| sent_at.elapsed() >= timer.timeout()) {
+                    if sent_at.is_some() {
+                        retry_cnt += 1;
+                        if retry_cnt == self.max_retries {
+                            return Err(TftpError::Timeout);
+                        }
+                        retransmitted = true;
+                        timer.backoff();
+                        self.socket.set_read_timeout(timer.timeout())?;
+                        if let Some(congestion) = &mut congestion {
+                            congestion.timeout();
+                        }
+                    }
+                    sent = match &congestion {
+                        Some(congestion) => u16::min(congestion.size(), window.len()),
+                        None => window.len(),
+                    };
+                    self.send_window(&window, block_index, sent)?;
+                    if sent < window.len() {
+                        // Receivers wait for a whole window before acknowledging
+                        // it, unless a block arrives out of order. Repeating the
+                        // last block once asks for an acknowledgement right away.
+                        self.repeat_block(&window, block_index, sent - 1)?;
+                        report.retransmissions += 1;
+                    }
+                    sent_at = Some(Instant::now());
+
+                    let window_end = report.blocks + sent as u64;
+                    report.retransmissions +=
+                        u64::min(sent_blocks, window_end).saturating_sub(report.blocks);
+                    sent_blocks = u64::max(sent_blocks, window_end);
                 }
 
                 match self.socket.recv() {
                     Ok(Packet::Ack(received_block_number)) => {
-                        let diff = received_block_number.wrapping_sub(block_number);
-                        if diff <= self.windowsize {
-                            block_number = received_block_number.wrapping_add(1);
-                            window.remove(diff + 1)?;
+                        let diff = self.rollover.distance(block_index, received_block_number);
+                        if diff < sent as u64 {
+                            if let Some(congestion) = &mut congestion {
+                                congestion.acknowledge(diff as u16 + 1, sent);
+                            }
+                            // Acknowledgements of retransmitted windows are ambiguous.
+                            if self.adaptive_timeout.is_some() && !retransmitted {
+                                if let Some(sent_at) = sent_at {
+                                    timer.sample(sent_at.elapsed());
+                                    self.socket.set_read_timeout(timer.timeout())?;
+                                }
+                            }
+                            block_index += diff + 1;
+                            report.blocks += diff + 1;
+                            report.bytes += window
+                                .get_elements()
+                                .iter()
+                                .take(diff as usize + 1)
+                                .map(|data| data.len() as u64)
+                                .sum::<u64>();
+                            window.remove(diff as u16 + 1)?;
                             break;
                         }
+
+                        // Answering acknowledgements of blocks that were already
+                        // acknowledged would send every following window twice
+                        // (Sorcerer's Apprentice Syndrome), so only the timeout
+                        // causes a retransmission.
+                        report.duplicate_acks += 1;
                     }
                     Ok(Packet::Error { code, msg }) => {
-                        return Err(format!("Received error code {code}: {msg}").into());
+                        return Err(TftpError::Remote { code, msg });
                     }
+                    // Retries are counted when the window is sent again.
+                    Err(TftpError::Timeout) => {}
                     _ => {
                         retry_cnt += 1;
-                        if retry_cnt == MAX_RETRIES {
-                            return Err(
-                                format!("Transfer timed out after {MAX_RETRIES} tries").into()
-                            );
+                        if retry_cnt == self.max_retries {
+                            return Err(TftpError::Timeout);
                         }
                     }
                 }
             }
 
+            if let Some(congestion) = &congestion {
+                report.window = Some(congestion.stats());
+            }
             if !filled && window.is_empty() {
                 break;
             }
@@ -171,24 +309,29 @@ impl<T: Socket + ?Sized> Worker<T> {
         Ok(())
     }
 
-    fn receive_file(self, file: File) -> Result<(), Box<dyn Error>> {
-        let mut block_number: u16 = 0;
-        let mut window = Window::new(self.windowsize, self.blk_size, file);
+    fn receive_file(mut self, file: File, report: &mut TransferReport) -> Result<(), TftpError> {
+        let mut block_index = 0;
+        let mut window = Window::new(self.windowsize, self.blk_size, file, self.netascii);
 
         loop {
             let mut size;
             let mut retry_cnt = 0;
+            let mut reacknowledged = false;
 
             loop {
+                let next_block_number = self.block_number(block_index + 1)?;
                 match self.socket.recv_with_size(self.blk_size) {
                     Ok(Packet::Data {
                         block_num: received_block_number,
                         data,
                     }) => {
-                        if received_block_number == block_number.wrapping_add(1) {
-                            block_number = received_block_number;
+                        if received_block_number == next_block_number {
+                            block_index += 1;
                             size = data.len();
+                            report.blocks += 1;
+                            report.bytes += size as u64;
                             window.add(data)?;
+                            reacknowledged = false;
 
                             if size < self.blk_size {
                                 break;
@@ -197,45 +340,843 @@ impl<T: Socket + ?Sized> Worker<T> {
                             if window.is_full() {
                                 break;
                             }
+                        } else if !reacknowledged {
+                            // Either a block of the window was lost, or the sender
+                            // repeats blocks whose acknowledgement was lost. In both
+                            // cases it can resume after the last block received in
+                            // order.
+                            if self
+                                .rollover
+                                .distance(block_index + 1, received_block_number)
+                                >= self.windowsize as u64
+                            {
+                                report.retransmissions += 1;
+                            }
+                            self.acknowledge(&mut window, block_index)?;
+                            reacknowledged = true;
                         }
                     }
                     Ok(Packet::Error { code, msg }) => {
-                        return Err(format!("Received error code {code}: {msg}").into());
+                        return Err(TftpError::Remote { code, msg });
                     }
-                    _ => {
+                    result => {
                         retry_cnt += 1;
-                        if retry_cnt == MAX_RETRIES {
-                            return Err(
-                                format!("Transfer timed out after {MAX_RETRIES} tries").into()
-                            );
+                        if retry_cnt == self.max_retries {
+                            return Err(TftpError::Timeout);
+                        }
+
+                        // The acknowledgement may have been lost. Before the first
+                        // block, it may have been an option acknowledgement.
+                        if matches!(result, Err(TftpError::Timeout)) {
+                            if block_index == 0 && !self.options.is_empty() {
+                                self.socket.send(&Packet::Oack(self.options.clone()))?;
+                            } else {
+                                self.acknowledge(&mut window, block_index)?;
+                            }
+                            report.retransmissions += 1;
                         }
                     }
                 }
             }
 
-            window.empty()?;
-            self.socket.send(&Packet::Ack(block_number))?;
+            self.acknowledge(&mut window, block_index)?;
             if size < self.blk_size {
                 break;
             };
         }
 
+        window.flush().map_err(|err| self.abort(err))?;
+        self.dally(self.block_number(block_index)?, report);
+
+        Ok(())
+    }
+
+    /// Acknowledges the last block again whenever the remote repeats it,
+    /// until the dallying period is over.
+    fn dally(&mut self, block_number: u16, report: &mut TransferReport) {
+        let deadline = Instant::now() + self.dally;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() || self.socket.set_read_timeout(remaining).is_err() {
+                break;
+            }
+
+            match self.socket.recv_with_size(self.blk_size) {
+                Ok(Packet::Data { block_num, .. }) if block_num == block_number => {
+                    if self.socket.send(&Packet::Ack(block_number)).is_err() {
+                        break;
+                    }
+                    report.retransmissions += 1;
+                }
+                Ok(_) | Err(TftpError::Timeout) | Err(TftpError::Protocol(_)) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Writes the received blocks to the file and acknowledges the block at
+    /// `block_index`.
+    fn acknowledge(&self, window: &mut Window, block_index: u64) -> Result<(), TftpError> {
+        window.empty().map_err(|err| self.abort(err))?;
+        self.socket
+            .send(&Packet::Ack(self.block_number(block_index)?))?;
+
         Ok(())
     }
+
+    fn send_window(&self, window: &Window, block_index: u64, count: u16) -> Result<(), TftpError> {
+        let frames = window.get_elements().iter().take(count as usize);
+        for (index, frame) in (block_index..).zip(frames) {
+            self.socket.send(&Packet::Data {
+                block_num: self.block_number(index)?,
+                data: frame.to_vec(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn repeat_block(
+        &self,
+        window: &Window,
+        block_index: u64,
+        offset: u16,
+    ) -> Result<(), TftpError> {
+        self.socket.send(&Packet::Data {
+            block_num: self.block_number(block_index + offset as u64)?,
+            data: window.get_elements()[offset as usize].to_vec(),
+        })
+    }
+
+    /// Returns the block number of the block at `index`, aborting the transfer
+    /// if it exceeds the limit while rollover is disabled.
+    fn block_number(&self, index: u64) -> Result<u16, TftpError> {
+        self.rollover.block_number(index).ok_or_else(|| {
+            let packet = Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "block number limit exceeded".to_string(),
+            };
+            // The transfer fails either way, so a lost error packet is ignored.
+            let _ = self.socket.send(&packet);
+
+            TftpError::Protocol("Block number limit exceeded without rollover".to_string())
+        })
+    }
+
+    /// Notifies the remote of a local file error before aborting the transfer.
+    fn abort(&self, err: TftpError) -> TftpError {
+        if let TftpError::Io(io_err) = &err {
+            let code = error_code(io_err);
+            let packet = Packet::Error {
+                code,
+                msg: error_message(code).to_string(),
+            };
+            // The transfer fails either way, so a lost error packet is ignored.
+            let _ = self.socket.send(&packet);
+        }
+
+        err
+    }
+}
+
+/// Returns the message sent to the remote for a local file error, which
+/// keeps local details such as paths out of the packet.
+fn error_message(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::FileNotFound => "file does not exist",
+        ErrorCode::AccessViolation => "file access violation",
+        ErrorCode::DiskFull => "disk full or allocation exceeded",
+        _ => "file error",
+    }
+}
+
+fn error_code(err: &io::Error) -> ErrorCode {
+    match err.kind() {
+        io::ErrorKind::NotFound => ErrorCode::FileNotFound,
+        io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => {
+            ErrorCode::AccessViolation
+        }
+        io::ErrorKind::StorageFull | io::ErrorKind::QuotaExceeded | io::ErrorKind::FileTooLarge => {
+            ErrorCode::DiskFull
+        }
+        _ => ErrorCode::NotDefined,
+    }
 }
 
-fn send_window<T: Socket>(
-    socket: &T,
-    window: &Window,
-    mut block_num: u16,
-) -> Result<(), Box<dyn Error>> {
-    for frame in window.get_elements() {
-        socket.send(&Packet::Data {
-            block_num,
-            data: frame.to_vec(),
-        })?;
-        block_num = block_num.wrapping_add(1);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::WindowStats;
+    use std::{
+        env,
+        net::{SocketAddr, UdpSocket},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    const TIMEOUT: Duration = Duration::from_millis(100);
+
+    #[test]
+    fn reports_sent_file() {
+        let file_name = initialize("reports_sent_file", &[0x01; 1000]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 512, TIMEOUT, 1, false);
+        let handle = worker.send().unwrap();
+
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 1, .. }
+        ));
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 1, .. }
+        ));
+        Socket::send(&client, &Packet::Ack(1)).unwrap();
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 2, .. }
+        ));
+        Socket::send(&client, &Packet::Ack(2)).unwrap();
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.direction, TransferDirection::Send);
+        assert_eq!(report.bytes, 1000);
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.retransmissions, 1);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reports_received_file() {
+        let file_name = initialize("reports_received_file", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 512, TIMEOUT, 1, false);
+        let handle = worker.receive().unwrap();
+
+        Socket::send(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: vec![0x01; 512],
+            },
+        )
+        .unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+        Socket::send(
+            &client,
+            &Packet::Data {
+                block_num: 2,
+                data: vec![0x02; 10],
+            },
+        )
+        .unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(2));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.direction, TransferDirection::Receive);
+        assert_eq!(report.bytes, 522);
+        assert_eq!(report.blocks, 2);
+        assert_eq!(fs::read(&file_name).unwrap().len(), 522);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reports_remote_error() {
+        let file_name = initialize("reports_remote_error", &[0x01; 100]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 512, TIMEOUT, 1, false);
+        let handle = worker.send().unwrap();
+
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 1, .. }
+        ));
+        Socket::send(
+            &client,
+            &Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "disk full".to_string(),
+            },
+        )
+        .unwrap();
+
+        let report = handle.join();
+        assert!(matches!(
+            report.error,
+            Some(TftpError::Remote {
+                code: ErrorCode::DiskFull,
+                ..
+            })
+        ));
+        assert_eq!(report.blocks, 0);
+
+        fs::remove_file(file_name).unwrap();
     }
 
-    Ok(())
+    #[test]
+    fn adapts_timeout_to_round_trip_time() {
+        let file_name = initialize("adapts_timeout_to_round_trip_time", &[0x01; 24]);
+        let (socket, client) = socket_pair();
+
+        let mut worker = Worker::new(
+            Box::new(socket),
+            file_name.clone(),
+            8,
+            Duration::from_secs(2),
+            1,
+            false,
+        );
+        worker.set_adaptive_timeout(Duration::from_millis(10), Duration::from_secs(2));
+        let handle = worker.send().unwrap();
+
+        for block_num in 1..=3 {
+            assert_eq!(
+                Socket::recv(&client).unwrap(),
+                Packet::Data {
+                    block_num,
+                    data: vec![0x01; 8]
+                }
+            );
+            Socket::send(&client, &Packet::Ack(block_num)).unwrap();
+        }
+
+        // The lost acknowledgement is noticed well before the initial timeout.
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 4, .. }
+        ));
+        let lost_at = Instant::now();
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Data { block_num: 4, .. }
+        ));
+        assert!(lost_at.elapsed() < Duration::from_secs(1));
+        Socket::send(&client, &Packet::Ack(4)).unwrap();
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.retransmissions, 1);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn grows_and_shrinks_congestion_window() {
+        let file_name = initialize("grows_and_shrinks_congestion_window", &[0x01; 60]);
+        let (socket, mut client) = socket_pair();
+        Socket::set_read_timeout(&mut client, TIMEOUT * 3).unwrap();
+
+        let mut worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 4, false);
+        worker.set_congestion_control(true);
+        let handle = worker.send().unwrap();
+
+        let receive_blocks = |count: u16| {
+            (0..count)
+                .map(|_| match Socket::recv(&client).unwrap() {
+                    Packet::Data { block_num, .. } => block_num,
+                    packet => panic!("unexpected packet {packet}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Windows smaller than the window size end with their last block
+        // sent twice, instead of being filled up to the window size.
+        assert_eq!(receive_blocks(2), [1, 1]);
+        Socket::send(&client, &Packet::Ack(1)).unwrap();
+        assert_eq!(receive_blocks(3), [2, 3, 3]);
+        Socket::send(&client, &Packet::Ack(3)).unwrap();
+        assert_eq!(receive_blocks(4), [4, 5, 6, 7]);
+        // Block 6 is lost, so the window is halved.
+        Socket::send(&client, &Packet::Ack(5)).unwrap();
+        assert_eq!(receive_blocks(3), [6, 7, 7]);
+        Socket::send(&client, &Packet::Ack(7)).unwrap();
+        assert_eq!(receive_blocks(1), [8]);
+        Socket::send(&client, &Packet::Ack(8)).unwrap();
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert!(Socket::recv(&client).is_err());
+        assert_eq!(report.blocks, 8);
+        assert_eq!(report.retransmissions, 5);
+        assert_eq!(
+            report.window,
+            Some(WindowStats {
+                min: 1,
+                max: 4,
+                last: 3,
+                increases: 3,
+                decreases: 1,
+            })
+        );
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn resumes_from_first_unacknowledged_block() {
+        let contents = (0..52).collect::<Vec<u8>>();
+        let file_name = initialize("resumes_from_first_unacknowledged_block", &contents);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 4, false);
+        let handle = worker.send().unwrap();
+
+        let receive_blocks = |count: usize| {
+            (0..count)
+                .map(|_| match Socket::recv(&client).unwrap() {
+                    Packet::Data { block_num, .. } => block_num,
+                    packet => panic!("unexpected packet {packet}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(receive_blocks(4), [1, 2, 3, 4]);
+        Socket::send(&client, &Packet::Ack(2)).unwrap();
+        assert_eq!(receive_blocks(4), [3, 4, 5, 6]);
+        Socket::send(&client, &Packet::Ack(6)).unwrap();
+        assert_eq!(receive_blocks(1), [7]);
+        Socket::send(&client, &Packet::Ack(7)).unwrap();
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 7);
+        assert_eq!(report.retransmissions, 2);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn acknowledges_last_block_before_gap() {
+        let file_name = initialize("acknowledges_last_block_before_gap", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 4, false);
+        let handle = worker.receive().unwrap();
+
+        let send_block = |block_num: u16| {
+            let data = vec![block_num as u8; if block_num < 6 { 8 } else { 3 }];
+            Socket::send(&client, &Packet::Data { block_num, data }).unwrap();
+        };
+
+        send_block(1);
+        send_block(3);
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+        send_block(4);
+        for block_num in 2..=5 {
+            send_block(block_num);
+        }
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(5));
+        send_block(6);
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(6));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 6);
+        let expected = [1, 2, 3, 4, 5]
+            .iter()
+            .flat_map(|&block| [block; 8])
+            .chain([6; 3])
+            .collect::<Vec<u8>>();
+        assert_eq!(fs::read(&file_name).unwrap(), expected);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn ignores_duplicate_acknowledgements() {
+        let file_name = initialize("ignores_duplicate_acknowledgements", &[0x01; 20]);
+        let (socket, mut client) = socket_pair();
+        Socket::set_read_timeout(&mut client, TIMEOUT * 3).unwrap();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 1, false);
+        let handle = worker.send().unwrap();
+
+        for (block_num, acks) in [(1, [1, 1, 1]), (2, [1, 2, 2]), (3, [2, 2, 3])] {
+            assert!(matches!(
+                Socket::recv(&client).unwrap(),
+                Packet::Data { block_num: received, .. } if received == block_num
+            ));
+            for ack in acks {
+                Socket::send(&client, &Packet::Ack(ack)).unwrap();
+            }
+        }
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.retransmissions, 0);
+        assert_eq!(report.duplicate_acks, 6);
+        assert!(Socket::recv(&client).is_err());
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn ignores_duplicate_window_acknowledgements() {
+        let file_name = initialize("ignores_duplicate_window_acknowledgements", &[0x01; 60]);
+        let (socket, mut client) = socket_pair();
+        Socket::set_read_timeout(&mut client, TIMEOUT * 3).unwrap();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 4, false);
+        let handle = worker.send().unwrap();
+
+        let receive_blocks = || {
+            (0..4)
+                .map(|_| match Socket::recv(&client).unwrap() {
+                    Packet::Data { block_num, .. } => block_num,
+                    packet => panic!("unexpected packet {packet}"),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(receive_blocks(), [1, 2, 3, 4]);
+        Socket::send(&client, &Packet::Ack(4)).unwrap();
+        Socket::send(&client, &Packet::Ack(4)).unwrap();
+        assert_eq!(receive_blocks(), [5, 6, 7, 8]);
+        for ack in [2, 4, 8] {
+            Socket::send(&client, &Packet::Ack(ack)).unwrap();
+        }
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 8);
+        assert_eq!(report.retransmissions, 0);
+        assert_eq!(report.duplicate_acks, 3);
+        assert!(Socket::recv(&client).is_err());
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reacknowledges_request_on_timeout() {
+        let file_name = initialize("reacknowledges_request_on_timeout", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 1, false);
+        let handle = worker.receive().unwrap();
+
+        // The acknowledgement of the request is lost.
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(0));
+        Socket::send(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: vec![0x01; 3],
+            },
+        )
+        .unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.retransmissions, 1);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reacknowledges_block_on_timeout() {
+        let file_name = initialize("reacknowledges_block_on_timeout", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 1, false);
+        let handle = worker.receive().unwrap();
+
+        Socket::send(
+            &client,
+            &Packet::Data {
+                block_num: 1,
+                data: vec![0x01; 8],
+            },
+        )
+        .unwrap();
+        // The first acknowledgement is lost.
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+        Socket::send(
+            &client,
+            &Packet::Data {
+                block_num: 2,
+                data: vec![0x02; 3],
+            },
+        )
+        .unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(2));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 2);
+        assert_eq!(report.retransmissions, 1);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reacknowledges_repeated_window() {
+        let file_name = initialize("reacknowledges_repeated_window", &[]);
+        let (mut socket, client) = socket_pair();
+        Socket::set_read_timeout(&mut socket, Duration::from_secs(5)).unwrap();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 8, TIMEOUT, 2, false);
+        let handle = worker.receive().unwrap();
+
+        let send_block = |block_num: u16| {
+            let data = vec![block_num as u8; if block_num < 3 { 8 } else { 3 }];
+            Socket::send(&client, &Packet::Data { block_num, data }).unwrap();
+        };
+
+        send_block(1);
+        send_block(2);
+        // The acknowledgement is lost, so the sender repeats the window.
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(2));
+        send_block(1);
+        send_block(2);
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(2));
+        send_block(3);
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(3));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 3);
+        assert_eq!(report.retransmissions, 1);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_lost_acknowledgements() {
+        let contents = (0..=255).cycle().take(200).collect::<Vec<u8>>();
+        for windowsize in [1, 4] {
+            let source = initialize(
+                &format!("recovers_from_lost_acknowledgements_{windowsize}_source"),
+                &contents,
+            );
+            let destination = initialize(
+                &format!("recovers_from_lost_acknowledgements_{windowsize}"),
+                &[],
+            );
+            let (socket, client) = socket_pair();
+            let socket = LossySocket {
+                socket,
+                nth: 3,
+                acks: AtomicUsize::new(0),
+            };
+
+            let mut receiver = Worker::new(
+                Box::new(socket),
+                destination.clone(),
+                8,
+                TIMEOUT,
+                windowsize,
+                false,
+            );
+            receiver.set_dally(Duration::from_millis(500));
+            let receiver = receiver.receive().unwrap();
+            let sender = Worker::new(
+                Box::new(client),
+                source.clone(),
+                8,
+                TIMEOUT,
+                windowsize,
+                false,
+            )
+            .send()
+            .unwrap();
+
+            let sent = sender.join();
+            let received = receiver.join();
+            assert!(sent.is_success(), "{:?}", sent.error);
+            assert!(received.is_success(), "{:?}", received.error);
+            assert!(received.retransmissions > 0);
+            assert_eq!(fs::read(&destination).unwrap(), contents);
+
+            fs::remove_file(source).unwrap();
+            fs::remove_file(destination).unwrap();
+        }
+    }
+
+    #[test]
+    fn reacknowledges_last_block_while_dallying() {
+        let file_name = initialize("reacknowledges_last_block_while_dallying", &[]);
+        let (socket, client) = socket_pair();
+
+        let mut worker = Worker::new(Box::new(socket), file_name.clone(), 512, TIMEOUT, 1, false);
+        worker.set_dally(Duration::from_secs(1));
+        let handle = worker.receive().unwrap();
+
+        let last_block = Packet::Data {
+            block_num: 1,
+            data: vec![0x01; 10],
+        };
+        Socket::send(&client, &last_block).unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+        Socket::send(&client, &last_block).unwrap();
+        assert_eq!(Socket::recv(&client).unwrap(), Packet::Ack(1));
+
+        let report = handle.join();
+        assert!(report.is_success());
+        assert_eq!(report.blocks, 1);
+        assert_eq!(report.retransmissions, 1);
+        assert_eq!(fs::read(&file_name).unwrap(), vec![0x01; 10]);
+
+        fs::remove_file(file_name).unwrap();
+    }
+
+    #[test]
+    fn reports_missing_file_to_remote() {
+        let file_name = initialize("reports_missing_file_to_remote", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name, 512, TIMEOUT, 1, false);
+        let handle = worker.send().unwrap();
+
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Error {
+                code: ErrorCode::FileNotFound,
+                ..
+            }
+        ));
+        assert!(matches!(handle.join().error, Some(TftpError::Io(_))));
+    }
+
+    #[test]
+    fn reports_read_error_to_remote() {
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), env::temp_dir(), 512, TIMEOUT, 1, false);
+        let handle = worker.send().unwrap();
+
+        assert_eq!(
+            Socket::recv(&client).unwrap(),
+            Packet::Error {
+                code: ErrorCode::NotDefined,
+                msg: "file error".to_string(),
+            }
+        );
+        assert!(matches!(handle.join().error, Some(TftpError::Io(_))));
+    }
+
+    #[test]
+    fn reports_create_error_to_remote() {
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), env::temp_dir(), 512, TIMEOUT, 1, false);
+
+        assert!(matches!(worker.receive(), Err(TftpError::Io(_))));
+        assert!(matches!(
+            Socket::recv(&client).unwrap(),
+            Packet::Error { .. }
+        ));
+    }
+
+    #[test]
+    fn reports_cleanup_error() {
+        let file_name = initialize("reports_cleanup_error", &[]);
+        let (socket, client) = socket_pair();
+
+        let worker = Worker::new(Box::new(socket), file_name.clone(), 512, TIMEOUT, 1, false);
+        let handle = worker.receive().unwrap();
+        fs::remove_file(&file_name).unwrap();
+        Socket::send(
+            &client,
+            &Packet::Error {
+                code: ErrorCode::DiskFull,
+                msg: "disk full".to_string(),
+            },
+        )
+        .unwrap();
+
+        let report = handle.join();
+        assert!(matches!(report.error, Some(TftpError::Remote { .. })));
+        assert!(matches!(report.cleanup_error, Some(TftpError::Io(_))));
+    }
+
+    #[test]
+    fn maps_io_errors_to_error_codes() {
+        let code = |kind| error_code(&io::Error::from(kind));
+
+        assert_eq!(code(io::ErrorKind::NotFound), ErrorCode::FileNotFound);
+        assert_eq!(
+            code(io::ErrorKind::PermissionDenied),
+            ErrorCode::AccessViolation
+        );
+        assert_eq!(code(io::ErrorKind::StorageFull), ErrorCode::DiskFull);
+        assert_eq!(code(io::ErrorKind::InvalidData), ErrorCode::NotDefined);
+    }
+
+    /// Drops every `nth` acknowledgement sent through the socket.
+    struct LossySocket {
+        socket: UdpSocket,
+        nth: usize,
+        acks: AtomicUsize,
+    }
+
+    impl Socket for LossySocket {
+        fn send(&self, packet: &Packet) -> Result<(), TftpError> {
+            if matches!(packet, Packet::Ack(_))
+                && self.acks.fetch_add(1, Ordering::Relaxed) % self.nth == self.nth - 1
+            {
+                return Ok(());
+            }
+
+            Socket::send(&self.socket, packet)
+        }
+
+        fn send_to(&self, packet: &Packet, to: &SocketAddr) -> Result<(), TftpError> {
+            Socket::send_to(&self.socket, packet, to)
+        }
+
+        fn recv_with_size(&self, size: usize) -> Result<Packet, TftpError> {
+            Socket::recv_with_size(&self.socket, size)
+        }
+
+        fn recv_from_with_size(&self, size: usize) -> Result<(Packet, SocketAddr), TftpError> {
+            Socket::recv_from_with_size(&self.socket, size)
+        }
+
+        fn remote_addr(&self) -> Result<SocketAddr, TftpError> {
+            Socket::remote_addr(&self.socket)
+        }
+
+        fn set_read_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
+            Socket::set_read_timeout(&mut self.socket, dur)
+        }
+
+        fn set_write_timeout(&mut self, dur: Duration) -> Result<(), TftpError> {
+            Socket::set_write_timeout(&mut self.socket, dur)
+        }
+    }
+
+    fn socket_pair() -> (UdpSocket, UdpSocket) {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.connect(client.local_addr().unwrap()).unwrap();
+        client.connect(socket.local_addr().unwrap()).unwrap();
+        socket.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        (socket, client)
+    }
+
+    fn initialize(name: &str, contents: &[u8]) -> PathBuf {
+        let file_name = env::temp_dir().join(format!("tftpd_{name}"));
+        if contents.is_empty() {
+            if file_name.exists() {
+                fs::remove_file(&file_name).unwrap();
+            }
+        } else {
+            fs::write(&file_name, contents).unwrap();
+        }
+
+        file_name
+    }
 }